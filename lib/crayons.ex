@@ -6,12 +6,94 @@ defmodule Crayons do
   In order to build this library, you will need to install the Rust project
   (<https://rustup.rs>).
 
+  ## Errors
+
+  Failures are `{:error, kind, details}`, where `kind` is an atom such as
+  `:unknown_theme` and `details` is a `t:error_details/0` map. Its `:value` is
+  the argument at fault, its `:message` describes the failure, and, for an
+  unknown name, its `:suggestions` are the closest known names, best first:
+
+  ```elixir
+  {:error, :unknown_theme, %{suggestions: ["Solarized (dark)" | _]}} =
+    Crayons.color("x", :rust, theme: "solarized dark")
+  ```
+
+  Functions that accept the result of `File.read/1` forward its
+  `{:error, posix}` unchanged. An argument or option of the wrong type, such as
+  `format: 3`, is a mistake in the calling code rather than a failure, and
+  raises `ArgumentError` instead.
+
   [`syntect`]: https://crates.io/crates/syntect
   """
 
-  @type format :: :html | :terminal
-  @type opt :: {:format, format} | {:theme, String.t()}
-  @type error :: {:error, atom}
+  @type lang :: atom | String.t() | {:path, String.t()} | nil
+  @type detection :: :modeline | :filename | :extension | :shebang | :first_line
+  @type format ::
+          :html
+          | :html_classed
+          | :terminal
+          | :terminal_256
+          | :terminal_16
+          | :terminal_8
+          | :terminal_mono
+          | :terminal_ansi
+          | :tokens
+  @type rgba :: {0..255, 0..255, 0..255, 0..255}
+  @type token :: %{
+          text: String.t(),
+          foreground: rgba,
+          background: rgba,
+          font_style: [:bold | :italic | :underline]
+        }
+  @type scoped_token :: %{range: {non_neg_integer, non_neg_integer}, scopes: [String.t()]}
+  @type schedule :: :auto | :normal | :dirty
+  @type line_role :: :highlight | :focus | :inserted | :deleted
+  @type ansi_color ::
+          :black
+          | :red
+          | :green
+          | :yellow
+          | :blue
+          | :magenta
+          | :cyan
+          | :white
+          | :bright_black
+          | :bright_red
+          | :bright_green
+          | :bright_yellow
+          | :bright_blue
+          | :bright_magenta
+          | :bright_cyan
+          | :bright_white
+          | :default
+  @type line_spec :: pos_integer | Range.t()
+  @type opt ::
+          {:format, format}
+          | {:theme, String.t()}
+          | {:class_prefix, String.t()}
+          | {:schedule, schedule}
+          | {:dirty_threshold, non_neg_integer}
+          | {:reset_lines, boolean}
+          | {:line_numbers, boolean}
+          | {:line_start, non_neg_integer}
+          | {:line_padding, non_neg_integer}
+          | {:line_separator, String.t()}
+          | {:line_anchor, String.t()}
+          | {:mark_lines, [{line_role, line_spec | [line_spec]}]}
+          | {:unknown_lang, :plaintext | :error | :detect}
+          | {:ansi_palette, [{String.t() | atom, ansi_color}] | %{String.t() => ansi_color}}
+  @type error :: {:error, atom, error_details}
+  @type error_details :: %{
+          required(:value) => term,
+          required(:message) => String.t(),
+          required(:suggestions) => [String.t()],
+          optional(atom) => term
+        }
+
+  # Texts longer than this many bytes are colored on a dirty scheduler by
+  # default. Highlighting runs in the low megabytes per second, so this keeps
+  # normal-scheduler calls within about a millisecond.
+  @dirty_threshold 4 * 1024
 
   @doc """
   Colors some text according to a language specifier and a theme.
@@ -22,19 +104,110 @@ defmodule Crayons do
   - `lang`: An atom or string that names a language. This must be one of the
     languages known to [`syntect`], or a language that you have added to the
     library with [`Crayons.add_lang`]. If it is `nil` or the empty string, then
-    the plaintext formatter is chosen.
+    the plaintext formatter is chosen. If it is `:auto`, the language is
+    detected from the text, and if it is `{:path, path}`, the language is
+    detected from the file path as well; see [`Crayons.detect_lang`]. An
+    `{:ok, text}` result does not name the detected language, so call
+    [`Crayons.detect_lang`] with the same text and path to learn it.
   - `opts`:
-    - `format:` must be one of `:html` or `:terminal`
+    - `format:` must be one of `:html`, `:html_classed`, `:terminal`,
+      `:terminal_256`, `:terminal_16`, `:terminal_8`, `:terminal_mono`,
+      `:terminal_ansi`, or `:tokens`. `:html` uses inline `style=` attributes;
+      `:html_classed` emits CSS classes derived from the language's scopes, to
+      be styled with a stylesheet from [`Crayons.css_for_theme`]. `:terminal`
+      uses 24-bit color escapes; `:terminal_256`, `:terminal_16`, and
+      `:terminal_8` reduce the theme's colors to the perceptually nearest of
+      xterm's 256 colors or the basic ANSI colors, for terminals and log viewers
+      without truecolor; and `:terminal_mono` uses no color at all, only bold,
+      italic, and underline. `:terminal_ansi` ignores the theme and colors
+      scopes with the sixteen named colors of the terminal's own palette, so
+      that the output matches each reader's color scheme. `:tokens` does not
+      render the text at all, and instead produces a list of `t:token/0` maps
+      for each line, for callers that render the text themselves.
     - `theme:` must be a string name that is known to [`syntect`] as a theme,
       either by default or added with [`Crayons.add_theme`].
+    - `class_prefix:` a string prepended to every class emitted by
+      `:html_classed`. Defaults to `""`. It is written into the markup as it
+      is, so it may only hold ASCII letters, digits, `_`, and `-`, and may not
+      start with a digit; any other prefix returns
+      `{:error, :invalid_option, details}`.
+    - `schedule:` where the native highlighter runs. `:normal` runs it on a
+      normal BEAM scheduler, which is only appropriate for short texts;
+      `:dirty` runs it on a dirty CPU scheduler, which cannot be starved by
+      long texts. The default, `:auto`, picks `:dirty` for texts longer than
+      `dirty_threshold:` bytes (default #{@dirty_threshold}).
+    - `line_numbers:` when `true`, numbers each line in a gutter. In HTML, the
+      gutter cannot be selected, so copying the snippet copies only its code,
+      and each number links to its line: the first line is `id="L1"`, and can
+      be linked as `#L1`. In a terminal, the gutter is a dimmed column in the
+      theme's gutter colors. `:tokens` ignores this option.
+    - `line_start:` the number of the first line. Defaults to `1`.
+    - `line_padding:` the minimum width of the numbers. They are always padded
+      to the width of the last number.
+    - `line_separator:` text between each number and its line. Defaults to
+      `" "`.
+    - `line_anchor:` the prefix of each line's HTML `id`. Defaults to `"L"`.
+    - `reset_lines:` when `true`, the terminal formats reset the terminal's
+      attributes at the end of every line, so that pagers such as `less -R`,
+      which may show any line first, do not bleed colors between lines.
+    - `ansi_palette:` changes which scopes `:terminal_ansi` colors how. It
+      pairs scope selectors, such as `"comment"` or
+      `"entity.name.function, support.function"`, with `t:ansi_color/0`
+      atoms. A pair whose selector matches one of the defaults exactly
+      replaces it; others take precedence over the defaults. By default,
+      comments are `:bright_black`, strings `:green`, constants `:cyan`,
+      keywords and storage `:magenta`, functions `:blue`, and types
+      `:yellow`.
+    - `mark_lines:` a keyword list of roles and the lines they apply to, as
+      line numbers and ranges, such as `[highlight: [1, 3..5], deleted: 7]`.
+      Lines count from `line_start:`. `:highlight` tints lines with the
+      theme's line highlight color, `:inserted` and `:deleted` tint them with
+      the theme's colors for diff markup, and `:focus` dims every line that is
+      not focused. In HTML, each marked line is wrapped in an element; with
+      `:html_classed`, its classes are `line` and `line-highlight`,
+      `line-inserted`, `line-deleted`, or `line-dimmed`.
+    - `unknown_lang:` what to do when `lang` is not known, as described
+      below. Defaults to `:plaintext`.
+
+  ## Line Endings
+
+  Every format keeps the text's line endings exactly as they are, including
+  `\\r\\n` and a missing or present trailing newline. With no gutter or marked
+  lines, removing the escape sequences from terminal output gives back the
+  original text byte for byte.
+
+  ## Unknown Languages
+
+  If `lang` is not known to the library, or cannot be detected, the text is
+  still made safe for the requested format: the HTML formats escape it and wrap
+  it as plain text, and the terminal format strips control sequences from it.
+  The result is then `{:fallback, colored, lang}`, where `lang` names the
+  language that was used instead, so that callers can tell it apart from a
+  successful highlight.
+
+  The `unknown_lang:` option chooses another policy for the call. `:error`
+  returns `{:error, :unknown_lang, details}` instead, whose suggestions are the
+  closest known language names and extensions, so that a mistyped `lang` is
+  caught rather than quietly shown as plain text; `:auto` and `{:path, path}`
+  that cannot be detected return `{:error, :undetected_lang, details}`.
+  `:detect` detects the language from the text, and highlights it as that
+  language, returning `{:fallback, colored, lang}` with the language detected;
+  if none is, the text is plain text as usual.
+
+  ```elixir
+  {:error, :unknown_lang, %{suggestions: ["Rust" | _]}} =
+    "fn main() {}" |> Crayons.color("rsut", unknown_lang: :error)
+
+  {:fallback, _, "Python"} =
+    "#!/usr/bin/env python3\nprint(1)\n" |> Crayons.color("pyton", unknown_lang: :detect)
+  ```
 
   [`syntect`]: https://crates.io/crates/syntect
   """
-  @spec color(
-          String.t(),
-          atom | String.t() | nil,
-          keyword
-        ) :: {:ok, String.t()} | {:error, atom, String.t() | nil}
+  @spec color(String.t(), lang, keyword) ::
+          {:ok, String.t() | [[token]]}
+          | {:fallback, String.t() | [[token]], String.t()}
+          | error
   def color(text, lang \\ nil, opts \\ [])
 
   # Empty-string and nil language markers use plaintext
@@ -46,9 +219,157 @@ defmodule Crayons do
     theme = opts |> Keyword.get(:theme, "Solarized (dark)")
     format = opts |> Keyword.get(:format, :html)
 
-    fn -> Crayons.Native.color(text, lang, format, theme) end |> offload
+    native_opts = native_opts(opts)
+
+    if dirty?(text, opts) do
+      fn -> Crayons.Native.color_dirty(text, lang, format, theme, native_opts) end |> offload
+    else
+      fn -> Crayons.Native.color(text, lang, format, theme, native_opts) end |> offload
+    end
+  end
+
+  @doc """
+  Parses text according to a language, without styling it.
+
+  Each token in the result is a run of text that shares one scope stack, such
+  as `["source.rust", "meta.function.rust", "entity.name.function.rust"]`. Its
+  `range` is the `{offset, length}` of the run within `text`, in bytes, and can
+  be given to `binary_part/3`. Line endings are not part of any token.
+
+  No theme is involved, so this is suitable for client-side theming, indexing,
+  or linting.
+
+  ## Arguments
+
+  - `text`: The text to parse.
+  - `lang`: The language of the text, as for [`Crayons.color`]. If it is not
+    known, the result is `{:fallback, tokens, lang}`.
+  - `opts`: The `schedule:` and `dirty_threshold:` options of
+    [`Crayons.color`].
+  """
+  @spec scopes(String.t(), lang, keyword) ::
+          {:ok, [scoped_token]} | {:fallback, [scoped_token], String.t()}
+  def scopes(text, lang, opts \\ [])
+  def scopes(text, lang, opts) when lang in [nil, ""], do: scopes(text, "txt", opts)
+
+  def scopes(text, lang, opts) do
+    if dirty?(text, opts) do
+      fn -> Crayons.Native.scopes_dirty(text, lang) end |> offload
+    else
+      fn -> Crayons.Native.scopes(text, lang) end |> offload
+    end
   end
 
+  @doc """
+  Detects the language of some text.
+
+  The evidence is considered in this order, and the first that names a known
+  language wins:
+
+  1. A vim (`vim: set ft=ruby:`) or emacs (`-*- mode: ruby -*-`) modeline in
+     the text.
+  2. The file name of `path`, such as `Makefile` or `Gemfile`.
+  3. The extension of `path`, preferring compound extensions like `.html.heex`.
+  4. The interpreter named by a `#!` line, such as `#!/usr/bin/env python3`.
+  5. The `first_line_match` patterns that languages declare for themselves,
+     such as `<?xml`.
+
+  The result names how the language was found:
+
+  ```elixir
+  {:ok, "Elixir", :extension} = Crayons.detect_lang("", "lib/crayons.ex")
+  {:ok, "Python", :shebang} = Crayons.detect_lang("#!/usr/bin/env python3\n")
+  ```
+
+  ## Arguments
+
+  - `text`: The text whose language is sought. Only its first and last few
+    lines are inspected.
+  - `path`: The path of the file the text came from, if there is one.
+  """
+  @spec detect_lang(String.t(), String.t() | nil) :: {:ok, String.t(), detection} | error
+  def detect_lang(text, path \\ nil), do: Crayons.Native.detect_lang(text, path)
+
+  @doc """
+  Renders the CSS stylesheet for a theme, to accompany text colored with the
+  `:html_classed` format.
+
+  Since the classed markup does not depend on the theme, switching themes only
+  requires switching stylesheets:
+
+  ```elixir
+  {:ok, css} = Crayons.css_for_theme("Solarized (light)", class_prefix: "hl-")
+  {:ok, html} = text |> Crayons.color(:rust, format: :html_classed, class_prefix: "hl-")
+  ```
+
+  ## Arguments
+
+  - `theme`: A theme name known to the library.
+  - `opts`:
+    - `class_prefix:` must match the prefix given to [`Crayons.color`].
+
+  Returns `{:ok, css}`, or `{:error, :invalid_option, details}` if the prefix
+  is not a valid start of a class name, as for [`Crayons.color`].
+  """
+  @spec css_for_theme(String.t(), keyword) :: {:ok, String.t()} | error
+  def css_for_theme(theme, opts \\ []) do
+    prefix = opts |> Keyword.get(:class_prefix, "")
+    Crayons.Native.css_for_theme(theme, prefix)
+  end
+
+  @doc """
+  Opens a stream for coloring text that arrives in pieces.
+
+  Text pushed into the stream with `stream_push/2` is colored one line at a
+  time, and the trailing partial line is held back until it is completed. Call
+  `stream_finish/1` to color whatever remains and close the output.
+
+  ```elixir
+  {:ok, stream} = Crayons.stream_new(:elixir, format: :terminal)
+  {:ok, ""} = Crayons.stream_push(stream, "defmodule Foo ")
+  {:ok, line} = Crayons.stream_push(stream, "do\nend")
+  {:ok, rest} = Crayons.stream_finish(stream)
+  ```
+
+  ## Arguments
+
+  - `lang`: The language of the text, as for [`Crayons.color`]. If it is not
+    known, the result is `{:fallback, stream, lang}` and the stream escapes its
+    text as plain text.
+  - `opts`: The `format:`, `theme:`, `class_prefix:`, `line_`, and
+    `mark_lines:` options of [`Crayons.color`]. Since a stream does not know
+    how many lines it will have, its line numbers are only padded to
+    `line_padding:`. `unknown_lang: :error` is honored as well, but the
+    stream has no text to detect a language from, so `:detect` falls back to
+    plain text.
+  """
+  @spec stream_new(lang, keyword) ::
+          {:ok, reference} | {:fallback, reference, String.t()} | error
+  def stream_new(lang, opts \\ [])
+  def stream_new(lang, opts) when lang in [nil, ""], do: stream_new("txt", opts)
+
+  def stream_new(lang, opts) do
+    theme = opts |> Keyword.get(:theme, "Solarized (dark)")
+    format = opts |> Keyword.get(:format, :html)
+    Crayons.Native.stream_new(lang, format, theme, native_opts(opts))
+  end
+
+  @doc """
+  Pushes a chunk of text into a stream, returning the colored form of every
+  line that it completes.
+  """
+  @spec stream_push(reference, String.t()) :: {:ok, String.t()} | error
+  def stream_push(stream, chunk),
+    do: fn -> Crayons.Native.stream_push(stream, chunk) end |> offload
+
+  @doc """
+  Finishes a stream, returning the colored form of any buffered partial line
+  and the closing markup of the stream's format. The stream cannot be used
+  afterwards.
+  """
+  @spec stream_finish(reference) :: {:ok, String.t()} | error
+  def stream_finish(stream), do: fn -> Crayons.Native.stream_finish(stream) end |> offload
+
   @doc """
   Adds a new language to the library's understanding.
 
@@ -61,17 +382,31 @@ defmodule Crayons do
 
   ## Arguments
 
-  - `file`: The *contents* of a `.tmLanguage` file. If this is a result tuple
-    (`{:ok | :error, contents | error}`), then it will do nothing in the error
-    case and forward in the success case.
+  - `file`: The *contents* of a `.sublime-syntax` file, or of a TextMate
+    grammar in either `.tmLanguage` (XML) or `.tmLanguage.json` form. TextMate
+    grammars are converted as they are loaded; if one uses constructs that
+    cannot be converted, such as `while` rules or injections, the error
+    message lists each of them along with where it occurs in the grammar. If
+    this is a result tuple (`{:ok | :error, contents | error}`), then it will
+    do nothing in the error case and forward in the success case.
   - `name`: A fallback name to give to the language, if `file` does not contain
     one.
   - `opts`:
     - `with_newlines`: Whether or not the new grammar expects source text for
-      colorization to contain newlines.
+      colorization to contain newlines. Grammars whose patterns match `\\n`
+      need this set; the library keeps both kinds apart, and feeds each
+      grammar lines in the form it expects. TextMate grammars always expect
+      newlines, so this is ignored for them.
+    - `on_conflict`: What to do if a language of the same name is already
+      loaded. `:replace` (the default) removes the added languages of that
+      name, so that reloading an updated grammar swaps it in cleanly; a
+      language built into the library is shadowed instead, as it cannot be
+      removed. `:error` leaves the library alone and returns
+      `{:error, :lang_conflict, details}`, and `:keep_both` keeps the old
+      language beside the new one, which lookups find first.
   """
   @spec add_lang(binary | {:ok, binary} | {:error, File.posix()}, String.t() | nil, keyword) ::
-          {:ok, String.t()} | {:error, String.t()}
+          {:ok, String.t()} | {:error, File.posix()} | error
   def add_lang(file, name \\ nil, opts \\ [])
 
   def add_lang({:ok, file}, name, opts), do: add_lang(file, name, opts)
@@ -79,9 +414,100 @@ defmodule Crayons do
 
   def add_lang(file, name, opts) do
     with_newlines = opts |> Keyword.get(:with_newlines, false)
-    fn -> Crayons.Native.add_lang(file, name, with_newlines) end |> offload
+    on_conflict = opts |> Keyword.get(:on_conflict, :replace)
+    fn -> Crayons.Native.add_lang(file, name, with_newlines, on_conflict) end |> offload
+  end
+
+  @doc """
+  Adds several languages to the library at once, rebuilding its language sets
+  only once. Either all of them are added or none are.
+
+  Prefer this to calling `add_lang/3` in a loop when loading many grammars,
+  such as during application boot.
+
+  ## Arguments
+
+  - `files`: A list of grammars, each of which is either the contents of a
+    file as `add_lang/3` takes them, or a `{contents, name}` or
+    `{contents, name, opts}` tuple giving its fallback name and its own
+    `with_newlines` option.
+  - `opts`:
+    - `with_newlines`: The default for grammars that do not set their own.
+    - `on_conflict`: As for `add_lang/3`. Grammars later in the list conflict
+      with earlier ones as well as with those already loaded.
+
+  Returns `{:ok, names}` in the order of `files`. If any grammar cannot be
+  read, returns `{:error, :invalid_lang_defn, details}`, whose `:errors` pair
+  the index of each one that failed with its message; if a name conflicts,
+  returns `{:error, :lang_conflict, details}`.
+  """
+  @spec add_langs(
+          [binary | {binary, String.t() | nil} | {binary, String.t() | nil, keyword}],
+          keyword
+        ) ::
+          {:ok, [String.t()]} | error
+  def add_langs(files, opts \\ []) do
+    with_newlines = opts |> Keyword.get(:with_newlines, false)
+    on_conflict = opts |> Keyword.get(:on_conflict, :replace)
+
+    definitions = for file <- files, do: lang_source(file, with_newlines)
+
+    fn -> Crayons.Native.add_langs(definitions, on_conflict) end |> offload
+  end
+
+  @doc """
+  Loads every grammar and theme in a Sublime Text package, such as one vendored
+  in `priv/`.
+
+  `path` is either a directory, which is searched recursively, or a
+  `.sublime-package` archive. Any `.sublime-package` archives inside a
+  directory are opened as well, so a directory of packages loads in one call.
+  `.sublime-syntax` files are added as languages, in one batch as by
+  `add_langs/2`, and `.tmTheme` and `.sublime-color-scheme` files are added as
+  themes named after their files. `.tmPreferences` files hold editor settings
+  that do not affect highlighting, and are skipped.
+
+  ## Options
+
+  - `on_conflict`: As for `add_lang/3`.
+
+  Returns `{:ok, report}`, where `report` lists the names of the `:langs` and
+  `:themes` that were added, the files that `:failed` to load as
+  `{path, message}` pairs, and the files that were `:skipped`, with paths
+  relative to the package. Files that fail do not stop the others from
+  loading. If the package cannot be opened, returns
+  `{:error, :invalid_package, details}`.
+
+  ```elixir
+  {:ok, %{failed: []}} =
+    :my_app |> Application.app_dir("priv/packages") |> Crayons.load_package()
+  ```
+  """
+  @spec load_package(Path.t(), keyword) ::
+          {:ok,
+           %{
+             langs: [String.t()],
+             themes: [String.t()],
+             failed: [{String.t(), String.t()}],
+             skipped: [String.t()]
+           }}
+          | error
+  def load_package(path, opts \\ []) do
+    on_conflict = opts |> Keyword.get(:on_conflict, :replace)
+    fn -> Crayons.Native.load_package(to_string(path), on_conflict) end |> offload
   end
 
+  @doc """
+  Removes a language that was added to the library, along with any others of
+  the same name, which is matched in any case. The languages built into the
+  library cannot be removed.
+
+  Returns `{:ok, name}` with the name as the language spelled it, or
+  `{:error, :lang_not_found, details}`.
+  """
+  @spec remove_lang(String.t()) :: {:ok, String.t()} | error
+  def remove_lang(name), do: fn -> Crayons.Native.remove_lang(name) end |> offload
+
   @doc """
   Adds a new theme to the library's understanding.
 
@@ -94,13 +520,17 @@ defmodule Crayons do
 
   ## Arguments
 
-  - `file`: The *contents* of a `.tmTheme` file. If this is a result tuple
-    (`{:ok | :error, contents | error}`), then it will do nothing in the error
-    case and forward in the success case.
+  - `file`: The *contents* of a `.tmTheme` file, a VS Code color theme, or a
+    `.sublime-color-scheme` file. VS Code editor colors such as
+    `editor.background` and `editor.lineHighlightBackground`, and a color
+    scheme's `globals`, become the theme's own colors; color schemes may use
+    `variables`, and both JSON forms may contain comments. If this is a result
+    tuple (`{:ok | :error, contents | error}`), then it will do nothing in the
+    error case and forward in the success case.
   - `name`: The name of the theme.
   """
   @spec add_theme(binary | {:ok, binary} | {:error, File.posix()}, String.t()) ::
-          {:ok, String.t()} | {:error, String.t()}
+          {:ok, String.t()} | {:error, File.posix()} | error
   def add_theme(file, name)
 
   def add_theme({:ok, file}, name), do: add_theme(file, name)
@@ -108,11 +538,143 @@ defmodule Crayons do
 
   def add_theme(file, name), do: fn -> Crayons.Native.add_theme(file, name) end |> offload
 
+  @doc """
+  Generates a theme from a Base16 or Base24 color scheme, and adds it to the
+  library's understanding.
+
+  The theme styles scopes by the Base16 styling guidelines: comments in
+  `base03`, keywords and storage in `base0E`, strings in `base0B`, functions in
+  `base0D`, and so on, on a `base00` background with `base05` text. Base24
+  schemes also use their darker backgrounds and bright accents. Use it as:
+
+  ```elixir
+  path |> File.read!() |> Crayons.add_base16_theme("brand")
+  ```
+
+  ## Arguments
+
+  - `file`: The *contents* of a Base16 or Base24 scheme's YAML file, with its
+    colors either at the top level or under `palette`. If this is a result
+    tuple (`{:ok | :error, contents | error}`), then it will do nothing in the
+    error case and forward in the success case.
+  - `name`: The name of the theme.
+  """
+  @spec add_base16_theme(binary | {:ok, binary} | {:error, File.posix()}, String.t()) ::
+          {:ok, String.t()} | {:error, File.posix()} | error
+  def add_base16_theme(file, name)
+
+  def add_base16_theme({:ok, file}, name), do: add_base16_theme(file, name)
+  def add_base16_theme({:error, err}, _name), do: {:error, err}
+
+  def add_base16_theme(file, name),
+    do: fn -> Crayons.Native.add_base16_theme(file, name) end |> offload
+
+  @doc """
+  Removes a theme from the library, whether it was added or built in.
+
+  Returns `{:ok, name}`, or `{:error, :unknown_theme, details}`.
+  """
+  @spec remove_theme(String.t()) :: {:ok, String.t()} | error
+  def remove_theme(name), do: fn -> Crayons.Native.remove_theme(name) end |> offload
+
+  @doc """
+  Restores the library to the languages and themes it starts with, discarding
+  everything added or removed since. Calls that are already running finish
+  with the languages and themes they started with.
+  """
+  @spec reset_registry() :: :ok
+  def reset_registry(), do: fn -> Crayons.Native.reset_registry() end |> offload
+
+  @doc """
+  Dumps every language and theme in the library, including those added since
+  it started, to a compressed binary that `load_registry/1` restores without
+  compiling anything.
+
+  Dumps are only read by the version of Crayons that wrote them, so build them
+  as part of a release, such as into `priv/`:
+
+  ```elixir
+  {:ok, dump} = Crayons.dump_registry()
+  File.write!("priv/crayons.dump", dump)
+  ```
+
+  Returns `{:ok, dump}`, or `{:error, :dump_failed, details}` if the registry
+  cannot be written, or `{:error, :out_of_memory, details}` if there is no
+  memory for the binary.
+  """
+  @spec dump_registry() :: {:ok, binary} | error
+  def dump_registry(), do: fn -> Crayons.Native.dump_registry() end |> offload
+
+  @doc """
+  Replaces every language and theme in the library with those in a dump from
+  `dump_registry/0`.
+
+  `dump` can also be a result tuple, as from `File.read/1`; it will do nothing
+  in the error case and forward in the success case.
+
+  Returns `:ok`, or `{:error, :invalid_dump, details}` if the dump is damaged
+  or was written by another version of Crayons, in which case the library is
+  left as it was.
+
+  ```elixir
+  :ok =
+    :my_app
+    |> Application.app_dir("priv/crayons.dump")
+    |> File.read()
+    |> Crayons.load_registry()
+  ```
+  """
+  @spec load_registry(binary | {:ok, binary} | {:error, File.posix()}) ::
+          :ok | {:error, File.posix()} | error
+  def load_registry({:ok, dump}), do: load_registry(dump)
+  def load_registry({:error, err}), do: {:error, err}
+  def load_registry(dump), do: fn -> Crayons.Native.load_registry(dump) end |> offload
+
+  @doc """
+  Lists the names of every language in the library, in lowercase.
+  """
   @spec list_langs() :: [String.t()]
   def list_langs(), do: Crayons.Native.list_langs()
 
+  @doc """
+  Lists the names of every theme in the library.
+  """
   @spec list_themes() :: [String.t()]
   def list_themes(), do: Crayons.Native.list_themes()
 
   defp offload(func), do: func |> Task.async() |> Task.await()
+
+  # Each grammar given to `add_langs` is passed to the native code as a
+  # `{contents, name, with_newlines}` tuple.
+  defp lang_source({file, name}, with_newlines), do: {file, name, with_newlines}
+
+  defp lang_source({file, name, opts}, with_newlines),
+    do: {file, name, Keyword.get(opts, :with_newlines, with_newlines)}
+
+  defp lang_source(file, with_newlines), do: {file, nil, with_newlines}
+
+  # The native code receives options as a map, with line-range specs flattened
+  # into `{role, first, last}` tuples and palette selectors made strings.
+  defp native_opts(opts) do
+    opts
+    |> Map.new()
+    |> Map.update(:mark_lines, [], fn marks ->
+      for {role, specs} <- marks, spec <- List.wrap(specs), do: line_range(role, spec)
+    end)
+    |> Map.update(:ansi_palette, [], fn palette ->
+      for {selector, color} <- palette, do: {to_string(selector), color}
+    end)
+  end
+
+  defp line_range(role, line) when is_integer(line), do: {role, line, line}
+  defp line_range(role, %Range{first: first, last: last}),
+    do: {role, min(first, last), max(first, last)}
+
+  defp dirty?(text, opts) do
+    case opts |> Keyword.get(:schedule, :auto) do
+      :normal -> false
+      :dirty -> true
+      :auto -> byte_size(text) > Keyword.get(opts, :dirty_threshold, @dirty_threshold)
+    end
+  end
 end
@@ -20,11 +20,77 @@ defmodule Crayons.Native do
   """
   @spec color(
           String.t(),
-          atom | String.t(),
-          :html | :terminal,
-          String.t()
-        ) :: {:ok, String.t()} | {:error, atom}
-  def color(_text, _lang, _format, _theme) do
+          Crayons.lang(),
+          Crayons.format(),
+          String.t(),
+          map
+        ) ::
+          {:ok, String.t() | [[Crayons.token()]]}
+          | {:fallback, String.t() | [[Crayons.token()]], String.t()}
+          | Crayons.error()
+  def color(_text, _lang, _format, _theme, _opts) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::color` on a dirty CPU scheduler.
+
+  See [`Crayons.color`].
+  """
+  @spec color_dirty(
+          String.t(),
+          Crayons.lang(),
+          Crayons.format(),
+          String.t(),
+          map
+        ) ::
+          {:ok, String.t() | [[Crayons.token()]]}
+          | {:fallback, String.t() | [[Crayons.token()]], String.t()}
+          | Crayons.error()
+  def color_dirty(_text, _lang, _format, _theme, _opts) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::scopes::scopes`.
+
+  See [`Crayons.scopes`].
+  """
+  @spec scopes(String.t(), Crayons.lang()) ::
+          {:ok, [Crayons.scoped_token()]} | {:fallback, [Crayons.scoped_token()], String.t()}
+  def scopes(_text, _lang) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::scopes::scopes` on a dirty CPU scheduler.
+
+  See [`Crayons.scopes`].
+  """
+  @spec scopes_dirty(String.t(), Crayons.lang()) ::
+          {:ok, [Crayons.scoped_token()]} | {:fallback, [Crayons.scoped_token()], String.t()}
+  def scopes_dirty(_text, _lang) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::detect::detect_lang`.
+
+  See [`Crayons.detect_lang`].
+  """
+  @spec detect_lang(String.t(), String.t() | nil) ::
+          {:ok, String.t(), Crayons.detection()} | Crayons.error()
+  def detect_lang(_text, _path) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::css_for_theme`.
+
+  See [`Crayons.css_for_theme`].
+  """
+  @spec css_for_theme(String.t(), String.t()) :: {:ok, String.t()} | Crayons.error()
+  def css_for_theme(_theme, _class_prefix) do
     raise NifNotLoaded
   end
 
@@ -36,9 +102,44 @@ defmodule Crayons.Native do
   @spec add_lang(
           binary,
           String.t() | nil,
-          true | false | nil
-        ) :: {:ok, String.t()} | {:error, String.t()}
-  def add_lang(_content, _name \\ nil, _include_newlines \\ false) do
+          true | false | nil,
+          :replace | :error | :keep_both
+        ) :: {:ok, String.t()} | Crayons.error()
+  def add_lang(_content, _name \\ nil, _include_newlines \\ false, _on_conflict \\ :replace) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::add_langs`.
+
+  See [`Crayons.add_langs`].
+  """
+  @spec add_langs(
+          [{binary, String.t() | nil, true | false}],
+          :replace | :error | :keep_both
+        ) :: {:ok, [String.t()]} | Crayons.error()
+  def add_langs(_definitions, _on_conflict \\ :replace) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::packages::load_package`.
+
+  See [`Crayons.load_package`].
+  """
+  @spec load_package(String.t(), :replace | :error | :keep_both) ::
+          {:ok, map} | Crayons.error()
+  def load_package(_path, _on_conflict \\ :replace) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::remove_lang`.
+
+  See [`Crayons.remove_lang`].
+  """
+  @spec remove_lang(String.t()) :: {:ok, String.t()} | Crayons.error()
+  def remove_lang(_name) do
     raise NifNotLoaded
   end
 
@@ -50,12 +151,102 @@ defmodule Crayons.Native do
   @spec add_theme(
           binary,
           String.t()
-        ) :: {:ok, String.t()} | {:error, String.t()}
+        ) :: {:ok, String.t()} | Crayons.error()
   def add_theme(_content, _name) do
     raise NifNotLoaded
   end
 
+  @doc """
+  Calls `crayons_nif::remove_theme`.
+
+  See [`Crayons.remove_theme`].
+  """
+  @spec remove_theme(String.t()) :: {:ok, String.t()} | Crayons.error()
+  def remove_theme(_name) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::add_base16_theme`.
+
+  See [`Crayons.add_base16_theme`].
+  """
+  @spec add_base16_theme(
+          binary,
+          String.t()
+        ) :: {:ok, String.t()} | Crayons.error()
+  def add_base16_theme(_content, _name) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::stream::stream_new`.
+
+  See [`Crayons.stream_new`].
+  """
+  @spec stream_new(Crayons.lang(), Crayons.format(), String.t(), map) ::
+          {:ok, reference} | {:fallback, reference, String.t()} | Crayons.error()
+  def stream_new(_lang, _format, _theme, _opts) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::stream::stream_push`.
+
+  See [`Crayons.stream_push`].
+  """
+  @spec stream_push(reference, String.t()) :: {:ok, String.t()} | Crayons.error()
+  def stream_push(_stream, _chunk) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::stream::stream_finish`.
+
+  See [`Crayons.stream_finish`].
+  """
+  @spec stream_finish(reference) :: {:ok, String.t()} | Crayons.error()
+  def stream_finish(_stream) do
+    raise NifNotLoaded
+  end
+
+  @doc """
+  Calls `crayons_nif::reset_registry`.
+
+  See [`Crayons.reset_registry`].
+  """
+  @spec reset_registry() :: :ok
+  def reset_registry(), do: raise(NifNotLoaded)
+
+  @doc """
+  Calls `crayons_nif::dump::dump_registry`.
+
+  See [`Crayons.dump_registry`].
+  """
+  @spec dump_registry() :: {:ok, binary} | Crayons.error()
+  def dump_registry(), do: raise(NifNotLoaded)
+
+  @doc """
+  Calls `crayons_nif::dump::load_registry`.
+
+  See [`Crayons.load_registry`].
+  """
+  @spec load_registry(binary) :: :ok | Crayons.error()
+  def load_registry(_dump), do: raise(NifNotLoaded)
+
+  @doc """
+  Calls `crayons_nif::list_langs`.
+
+  See [`Crayons.list_langs`].
+  """
+  @spec list_langs() :: [String.t()]
   def list_langs(), do: raise(NifNotLoaded)
 
+  @doc """
+  Calls `crayons_nif::list_themes`.
+
+  See [`Crayons.list_themes`].
+  """
+  @spec list_themes() :: [String.t()]
   def list_themes(), do: raise(NifNotLoaded)
 end
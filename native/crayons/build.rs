@@ -0,0 +1,152 @@
+//! Compiles the languages and themes that the library starts with.
+//!
+//! These are `syntect`'s defaults, the bundled Elixir grammars when the
+//! `elixir-grammars` feature is enabled, and a project's own grammars and
+//! themes. When `CRAYONS_EMBED_DIR` names a directory, every `.sublime-syntax`,
+//! `.tmTheme`, and `.sublime-color-scheme` file under it is included. A
+//! relative path is resolved from this crate's directory. Embedded grammars are
+//! compiled for lines that end in `\n`, as Sublime Text feeds them, unless
+//! `CRAYONS_EMBED_NEWLINES` is `false`.
+//!
+//! The languages are written to `syntaxes.bin` in the build's output directory
+//! in the layout that `Syntaxes::restore` reads, and the themes to `themes.bin`
+//! as the library's theme map, so that the library compiles nothing when it
+//! starts.
+
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use syntect::{
+    highlighting::ThemeSet,
+    parsing::{SyntaxDefinition, SyntaxSet},
+};
+
+#[path = "src/themes.rs"]
+mod themes;
+
+const EMBED_DIR: &str = "CRAYONS_EMBED_DIR";
+const EMBED_NEWLINES: &str = "CRAYONS_EMBED_NEWLINES";
+
+/// The bundled Elixir grammars, along with their template and session formats,
+/// which are written for lines without their `\n`.
+const ELIXIR: &[&str] = &[
+    "grammars/Elixir.sublime-syntax",
+    "grammars/EEx.sublime-syntax",
+    "grammars/HTML-EEx.sublime-syntax",
+    "grammars/HEEx.sublime-syntax",
+    "grammars/IEx.sublime-syntax",
+];
+
+/// A grammar compiled for both modes, and the mode it was written for, as
+/// `Syntaxes` keeps the grammars added to `syntect`'s own.
+type Definition = (SyntaxDefinition, SyntaxDefinition, bool);
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("cargo:rerun-if-env-changed={}", EMBED_DIR);
+    println!("cargo:rerun-if-env-changed={}", EMBED_NEWLINES);
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").expect("set by cargo"));
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("set by cargo"));
+
+    let mut grammars = vec![];
+    let mut theme_files = vec![];
+    if env::var_os("CARGO_FEATURE_ELIXIR_GRAMMARS").is_some() {
+        for path in ELIXIR {
+            let path = manifest_dir.join(path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            grammars.push((path, false));
+        }
+    }
+    if let Some(dir) = env::var_os(EMBED_DIR).filter(|dir| !dir.is_empty()) {
+        let newlines = match env::var(EMBED_NEWLINES).as_deref() {
+            Err(_) | Ok("") | Ok("true") => true,
+            Ok("false") => false,
+            Ok(other) => panic!(
+                "{} must be `true` or `false`, not `{}`",
+                EMBED_NEWLINES, other
+            ),
+        };
+        let mut embedded = vec![];
+        collect(&manifest_dir.join(dir), &mut embedded, &mut theme_files)?;
+        embedded.sort();
+        theme_files.sort();
+        grammars.extend(embedded.into_iter().map(|path| (path, newlines)));
+    }
+
+    // Later grammars replace earlier ones of the same name, so that embedded
+    // grammars take the place of bundled ones.
+    let mut definitions: Vec<Definition> = vec![];
+    for (path, wants_newlines) in grammars {
+        let source = fs::read_to_string(&path)?;
+        let stem = path.file_stem().and_then(|stem| stem.to_str());
+        let load = |newlines| {
+            SyntaxDefinition::load_from_str(&source, newlines, stem)
+                .unwrap_or_else(|err| panic!("grammar {} is invalid: {}", path.display(), err))
+        };
+        let (nonewlines, newlines) = (load(false), load(true));
+        definitions.retain(|(def, _, _)| !def.name.eq_ignore_ascii_case(&nonewlines.name));
+        definitions.push((nonewlines, newlines, wants_newlines));
+    }
+
+    let mut nonewlines = SyntaxSet::load_defaults_nonewlines().into_builder();
+    for (def, _, _) in &definitions {
+        nonewlines.add(def.clone());
+    }
+    let newlines = if definitions.iter().any(|(_, _, wants)| *wants) {
+        let mut newlines = SyntaxSet::load_defaults_newlines().into_builder();
+        for (_, def, _) in &definitions {
+            newlines.add(def.clone());
+        }
+        Some(newlines.build())
+    } else {
+        None
+    };
+    let mut out = compressed(&out_dir.join("syntaxes.bin"))?;
+    bincode::serialize_into(&mut out, &(nonewlines.build(), newlines, definitions))?;
+    out.finish()?.flush()?;
+
+    let mut themes = ThemeSet::load_defaults().themes;
+    for path in theme_files {
+        let name = path.file_stem().and_then(|stem| stem.to_str());
+        let name = name.unwrap_or_else(|| panic!("{} is not a UTF-8 path", path.display()));
+        let theme = themes::load(&fs::read(&path)?)
+            .unwrap_or_else(|err| panic!("theme {} is invalid: {}", path.display(), err));
+        themes.insert(name.to_owned(), theme);
+    }
+    let mut out = compressed(&out_dir.join("themes.bin"))?;
+    bincode::serialize_into(&mut out, &themes)?;
+    out.finish()?.flush()?;
+    Ok(())
+}
+
+/// Collects the grammars and themes under a directory.
+fn collect(dir: &Path, grammars: &mut Vec<PathBuf>, themes: &mut Vec<PathBuf>) -> io::Result<()> {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, grammars, themes)?;
+            continue;
+        }
+        let list = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("sublime-syntax") => &mut *grammars,
+            Some("tmTheme") | Some("sublime-color-scheme") => &mut *themes,
+            _ => continue,
+        };
+        println!("cargo:rerun-if-changed={}", path.display());
+        list.push(path);
+    }
+    Ok(())
+}
+
+/// Creates a compressed file for the library to read when it starts.
+fn compressed(path: &Path) -> io::Result<ZlibEncoder<BufWriter<File>>> {
+    let file = File::create(path)?;
+    Ok(ZlibEncoder::new(BufWriter::new(file), Compression::best()))
+}
@@ -0,0 +1,233 @@
+//! Themes generated from Base16 and Base24 color schemes.
+//!
+//! A [Base16] scheme is a palette of sixteen colors, `base00` through
+//! `base0F`, each with a fixed role: `base00` to `base07` run from the
+//! background to the brightest foreground, and `base08` to `base0F` are accent
+//! colors for kinds of code. Base24 adds two darker backgrounds and six bright
+//! accents. The scope rules here follow the Base16 styling guidelines, as the
+//! project's own TextMate template applies them.
+//!
+//! Schemes are read in both the original flat form, where the colors sit at
+//! the top level beside `scheme` and `author`, and the newer form, where they
+//! sit in a `palette` beside `system` and `name`.
+//!
+//! [Base16]: https://github.com/tinted-theming/home
+
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use syntect::highlighting::{
+    Color, FontStyle, ScopeSelectors, StyleModifier, Theme, ThemeItem, ThemeSettings,
+};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Why a scheme could not be read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SchemeError {
+    /// The scheme is not well-formed YAML, or not a mapping.
+    Yaml(String),
+    /// The scheme lacks one of its colors.
+    Missing(String),
+    /// One of the scheme's colors is not a hex color.
+    InvalidColor { key: String, value: String },
+}
+
+/// The colors of a scheme, indexed by their number: `base0D` is `[0x0D]`.
+struct Palette {
+    colors: Vec<Color>,
+}
+
+/// How each kind of scope is styled: the selector, the foreground and
+/// optional background as palette indices, and the font style.
+const RULES: &[(&str, u8, Option<u8>, FontStyle)] = &[
+    ("comment, punctuation.definition.comment", 0x03, None, FontStyle::empty()),
+    ("punctuation, meta.brace, keyword.operator", 0x05, None, FontStyle::empty()),
+    ("variable", 0x08, None, FontStyle::empty()),
+    ("variable.parameter", 0x05, None, FontStyle::empty()),
+    ("keyword, storage, meta.selector", 0x0E, None, FontStyle::empty()),
+    (
+        "entity.name.function, meta.require, support.function.any-method, variable.function, keyword.other.special-method",
+        0x0D,
+        None,
+        FontStyle::empty(),
+    ),
+    (
+        "support.class, entity.name.class, entity.name.type, entity.name.struct",
+        0x0A,
+        None,
+        FontStyle::empty(),
+    ),
+    ("support.function, support.type", 0x0C, None, FontStyle::empty()),
+    (
+        "string, constant.other.symbol, entity.other.inherited-class",
+        0x0B,
+        None,
+        FontStyle::empty(),
+    ),
+    ("constant, keyword.other.unit", 0x09, None, FontStyle::empty()),
+    ("entity.name.tag", 0x08, None, FontStyle::empty()),
+    ("entity.other.attribute-name", 0x09, None, FontStyle::empty()),
+    (
+        "entity.other.attribute-name.id, punctuation.definition.entity",
+        0x0D,
+        None,
+        FontStyle::empty(),
+    ),
+    (
+        "string.regexp, constant.character.escape, constant.other.color",
+        0x0C,
+        None,
+        FontStyle::empty(),
+    ),
+    (
+        "punctuation.section.embedded, variable.interpolation",
+        0x0F,
+        None,
+        FontStyle::empty(),
+    ),
+    (
+        "markup.heading, punctuation.definition.heading, entity.name.section",
+        0x0D,
+        None,
+        FontStyle::BOLD,
+    ),
+    ("markup.bold, punctuation.definition.bold", 0x0A, None, FontStyle::BOLD),
+    ("markup.italic, punctuation.definition.italic", 0x0E, None, FontStyle::ITALIC),
+    ("markup.raw.inline", 0x0B, None, FontStyle::empty()),
+    ("string.other.link, markup.list", 0x08, None, FontStyle::empty()),
+    ("meta.link, markup.quote", 0x09, None, FontStyle::empty()),
+    ("markup.inserted", 0x0B, None, FontStyle::empty()),
+    ("markup.deleted", 0x08, None, FontStyle::empty()),
+    ("markup.changed", 0x0E, None, FontStyle::empty()),
+    ("invalid.illegal", 0x07, Some(0x08), FontStyle::empty()),
+    ("invalid.broken", 0x00, Some(0x0A), FontStyle::empty()),
+    ("invalid.deprecated", 0x07, Some(0x0F), FontStyle::empty()),
+    ("invalid.unimplemented", 0x07, Some(0x04), FontStyle::empty()),
+];
+
+/// Rules that only Base24 schemes add, using their bright accents. They
+/// follow the Base16 rules, so they win where both match.
+const BASE24_RULES: &[(&str, u8, Option<u8>, FontStyle)] = &[
+    (
+        "markup.heading, entity.name.section",
+        0x16,
+        None,
+        FontStyle::BOLD,
+    ),
+    ("constant.character.escape", 0x15, None, FontStyle::empty()),
+    ("invalid.illegal", 0x07, Some(0x12), FontStyle::empty()),
+];
+
+/// Generates a theme from the YAML of a Base16 or Base24 scheme.
+pub fn theme(source: &str) -> Result<Theme, SchemeError> {
+    let docs =
+        YamlLoader::load_from_str(source).map_err(|err| SchemeError::Yaml(err.to_string()))?;
+    let root = match docs.first() {
+        Some(root @ Yaml::Hash(_)) => root,
+        _ => return Err(SchemeError::Yaml("the scheme is not a mapping".into())),
+    };
+    let colors = match &root["palette"] {
+        palette @ Yaml::Hash(_) => palette,
+        _ => root,
+    };
+    let base24 = match root["system"].as_str() {
+        Some(system) => system == "base24",
+        None => colors["base10"] != Yaml::BadValue,
+    };
+    let palette = Palette::new(colors, if base24 { 0x18 } else { 0x10 })?;
+
+    let mut scopes = vec![];
+    let extra: &[_] = if base24 { BASE24_RULES } else { &[] };
+    for &(selector, foreground, background, font_style) in RULES.iter().chain(extra) {
+        scopes.push(ThemeItem {
+            scope: ScopeSelectors::from_str(selector).expect("built-in selectors are valid"),
+            style: StyleModifier {
+                foreground: Some(palette[foreground]),
+                background: background.map(|bg| palette[bg]),
+                font_style: Some(font_style),
+            },
+        });
+    }
+
+    let name = root["name"].as_str().or_else(|| root["scheme"].as_str());
+    Ok(Theme {
+        name: name.map(Into::into),
+        author: root["author"].as_str().map(Into::into),
+        settings: ThemeSettings {
+            background: Some(palette[0x00]),
+            foreground: Some(palette[0x05]),
+            caret: Some(palette[0x05]),
+            line_highlight: Some(palette[0x01]),
+            selection: Some(palette[0x02]),
+            selection_foreground: Some(palette[0x05]),
+            gutter: Some(palette[if base24 { 0x10 } else { 0x01 }]),
+            gutter_foreground: Some(palette[0x03]),
+            find_highlight: Some(palette[0x0A]),
+            find_highlight_foreground: Some(palette[0x00]),
+            guide: Some(palette[0x02]),
+            active_guide: Some(palette[0x03]),
+            ..ThemeSettings::default()
+        },
+        scopes,
+    })
+}
+
+impl Palette {
+    /// Reads the first `count` colors of a scheme.
+    fn new(scheme: &Yaml, count: u8) -> Result<Self, SchemeError> {
+        let colors = (0..count)
+            .map(|idx| {
+                let key = format!("base{:02X}", idx);
+                // Hex values made only of digits read as numbers, and those with
+                // a single `e` may read as reals, so each form is turned back
+                // into its text.
+                let value = match &scheme[key.as_str()] {
+                    Yaml::String(value) | Yaml::Real(value) => value.clone(),
+                    Yaml::Integer(value) => format!("{:06}", value),
+                    Yaml::BadValue => return Err(SchemeError::Missing(key)),
+                    other => format!("{:?}", other),
+                };
+                hex(&value).ok_or(SchemeError::InvalidColor { key, value })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { colors })
+    }
+}
+
+impl std::ops::Index<u8> for Palette {
+    type Output = Color;
+
+    fn index(&self, idx: u8) -> &Color {
+        &self.colors[idx as usize]
+    }
+}
+
+/// Reads an `rrggbb` color, with or without a leading `#`.
+fn hex(value: &str) -> Option<Color> {
+    let digits = value.trim_start_matches('#');
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |idx: usize| u8::from_str_radix(&digits[idx..idx + 2], 16).ok();
+    Some(Color {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+        a: 0xff,
+    })
+}
+
+impl Display for SchemeError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Yaml(err) => write!(fmt, "invalid Base16 scheme: {}", err),
+            Self::Missing(key) => write!(fmt, "Base16 scheme has no `{}`", key),
+            Self::InvalidColor { key, value } => {
+                write!(fmt, "Base16 scheme has an invalid `{}`: {}", key, value)
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Choosing a language for text when the caller does not name one.
+//!
+//! The strongest evidence is an editor modeline in the text, since it states
+//! the author's intent. After that come the file's name, its extension, an
+//! interpreter named by a shebang, and finally the `first_line_match` patterns
+//! that grammars declare for themselves.
+
+use std::{borrow::Cow, path::Path};
+
+use rustler::{Atom, Encoder, Env, Error as NifError, NifResult, Term};
+
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+use crate::{atoms, errors::Failure, fail, ErrorKind, NifStatus, SYNTAXES};
+
+/// A language marker received from the BEAM.
+#[derive(Clone, Debug)]
+pub enum Lang<'env> {
+    /// An atom or string naming a language.
+    Token(Cow<'env, str>),
+    /// `:auto`, to detect the language from the text.
+    Auto,
+    /// `{:path, path}`, to detect the language from a file path and the text.
+    Path(&'env str),
+}
+
+impl<'env> Lang<'env> {
+    pub fn decode(term: Term<'env>) -> NifResult<Self> {
+        if term.is_atom() {
+            let atom: Atom = term.decode()?;
+            if atom == atoms::auto() {
+                return Ok(Self::Auto);
+            }
+            return Ok(Self::Token(Cow::Owned(term.atom_to_string()?)));
+        }
+        if let Ok((tag, path)) = term.decode::<(Atom, &'env str)>() {
+            if tag == atoms::path() {
+                return Ok(Self::Path(path));
+            }
+            return Err(NifError::BadArg);
+        }
+        Ok(Self::Token(Cow::Borrowed(term.decode()?)))
+    }
+
+    /// Finds the language in a syntax set. `text` is only consulted when the
+    /// language is to be detected.
+    pub fn resolve<'s>(
+        &self,
+        syntax_set: &'s SyntaxSet,
+        text: &str,
+    ) -> Option<&'s SyntaxReference> {
+        match self {
+            Self::Token(token) => syntax_set.find_syntax_by_token(token),
+            Self::Auto => detect(syntax_set, None, text).map(|(syntax, _)| syntax),
+            Self::Path(path) => detect(syntax_set, Some(*path), text).map(|(syntax, _)| syntax),
+        }
+    }
+
+    /// Finds the language in a syntax set, following `policy` if it is not
+    /// there.
+    pub fn resolve_or<'s>(
+        &self,
+        env: Env<'env>,
+        syntax_set: &'s SyntaxSet,
+        text: &str,
+        policy: UnknownLang,
+    ) -> Result<Resolved<'s>, Failure<'env>> {
+        if let Some(syntax) = self.resolve(syntax_set, text) {
+            return Ok(Resolved {
+                syntax,
+                status: NifStatus::Ok,
+                plain: false,
+            });
+        }
+        let detected = match (policy, self) {
+            (UnknownLang::Error, Self::Token(token)) => {
+                return Err(unknown_lang(env, syntax_set, token))
+            }
+            (UnknownLang::Error, Self::Auto) => return Err(undetected(env, None)),
+            (UnknownLang::Error, Self::Path(path)) => return Err(undetected(env, Some(*path))),
+            (UnknownLang::Detect, Self::Token(_)) => {
+                detect(syntax_set, None, text).map(|(syntax, _)| syntax)
+            }
+            _ => None,
+        };
+        Ok(match detected {
+            Some(syntax) => Resolved {
+                syntax,
+                status: NifStatus::Fallback,
+                plain: false,
+            },
+            None => Resolved {
+                syntax: syntax_set.find_syntax_plain_text(),
+                status: NifStatus::Fallback,
+                plain: true,
+            },
+        })
+    }
+}
+
+/// What to do when the language asked for is not known.
+#[derive(rustler::NifUnitEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnknownLang {
+    /// Color the text as plain text.
+    Plaintext,
+    /// Fail with `{:error, :unknown_lang, details}`.
+    Error,
+    /// Detect the language from the text, and color it as plain text if none
+    /// is detected.
+    Detect,
+}
+
+/// The language chosen to color some text.
+pub struct Resolved<'s> {
+    pub syntax: &'s SyntaxReference,
+    /// `Ok` if this is the language asked for, and `Fallback` if it is used in
+    /// its place.
+    pub status: NifStatus,
+    /// Whether no language was found, and the text is colored as plain text.
+    pub plain: bool,
+}
+
+/// How a language was detected.
+#[derive(rustler::NifUnitEnum, Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Detection {
+    /// A vim or emacs modeline in the text named the language.
+    Modeline,
+    /// The file's whole name is associated with the language.
+    Filename,
+    /// The file's extension is associated with the language.
+    Extension,
+    /// The interpreter named by a `#!` line is associated with the language.
+    Shebang,
+    /// The language's own `first_line_match` pattern matched the text.
+    FirstLine,
+}
+
+/// File names that identify a language but are not listed in any grammar's
+/// extensions, mapped to a token naming that language.
+const FILENAMES: &[(&str, &str)] = &[
+    ("BUILD", "python"),
+    ("BUILD.bazel", "python"),
+    ("Brewfile", "ruby"),
+    ("Capfile", "ruby"),
+    ("Gemfile", "ruby"),
+    ("Guardfile", "ruby"),
+    ("Jenkinsfile", "groovy"),
+    ("Podfile", "ruby"),
+    ("Rakefile", "ruby"),
+    ("Vagrantfile", "ruby"),
+    ("WORKSPACE", "python"),
+    ("mix.lock", "elixir"),
+    (".bash_profile", "bash"),
+    (".bashrc", "bash"),
+    (".profile", "bash"),
+    (".zshrc", "bash"),
+];
+
+/// Interpreters whose names are not tokens for their language.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("ash", "bash"),
+    ("dash", "bash"),
+    ("ksh", "bash"),
+    ("node", "js"),
+    ("nodejs", "js"),
+    ("rdmd", "d"),
+    ("runghc", "haskell"),
+    ("runhaskell", "haskell"),
+    ("sh", "bash"),
+    ("zsh", "bash"),
+];
+
+/// Detects the language of some text, from its path if one is known and from
+/// its contents.
+pub fn detect<'s>(
+    syntax_set: &'s SyntaxSet,
+    path: Option<&str>,
+    text: &str,
+) -> Option<(&'s SyntaxReference, Detection)> {
+    if let Some(syntax) = by_modeline(syntax_set, text) {
+        return Some((syntax, Detection::Modeline));
+    }
+    if let Some(path) = path {
+        if let Some(found) = by_path(syntax_set, path) {
+            return Some(found);
+        }
+    }
+    let first_line = text.lines().next().unwrap_or("");
+    if let Some(syntax) = by_shebang(syntax_set, first_line) {
+        return Some((syntax, Detection::Shebang));
+    }
+    syntax_set
+        .find_syntax_by_first_line(first_line)
+        .map(|syntax| (syntax, Detection::FirstLine))
+}
+
+fn by_path<'s>(syntax_set: &'s SyntaxSet, path: &str) -> Option<(&'s SyntaxReference, Detection)> {
+    let name = Path::new(path).file_name()?.to_str()?;
+
+    // Grammars frequently list whole file names, like `Makefile`, among their
+    // extensions.
+    // Later grammars win, as they do in `find_syntax_by_extension`.
+    let by_name = syntax_set
+        .syntaxes()
+        .iter()
+        .rev()
+        .find(|syntax| syntax.file_extensions.iter().any(|ext| ext == name))
+        .or_else(|| {
+            FILENAMES
+                .iter()
+                .find(|(file, _)| *file == name)
+                .and_then(|(_, token)| syntax_set.find_syntax_by_token(token))
+        });
+    if let Some(syntax) = by_name {
+        return Some((syntax, Detection::Filename));
+    }
+
+    // Try compound extensions, such as `html.heex`, before simple ones.
+    let name = name.trim_start_matches('.');
+    name.match_indices('.')
+        .map(|(idx, _)| &name[idx + 1..])
+        .find_map(|ext| {
+            syntax_set
+                .find_syntax_by_extension(ext)
+                .or_else(|| syntax_set.find_syntax_by_extension(&ext.to_lowercase()))
+        })
+        .map(|syntax| (syntax, Detection::Extension))
+}
+
+fn by_shebang<'s>(syntax_set: &'s SyntaxSet, first_line: &str) -> Option<&'s SyntaxReference> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        // `env` may carry its own flags, like `-S`, before the program.
+        interpreter = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    // `python3.9` and `ruby2.7` name the same languages as `python` and `ruby`.
+    let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    if interpreter.is_empty() {
+        return None;
+    }
+
+    let token = INTERPRETERS
+        .iter()
+        .find(|(name, _)| *name == interpreter)
+        .map(|(_, token)| *token)
+        .unwrap_or(interpreter);
+    syntax_set.find_syntax_by_token(token)
+}
+
+/// Looks for vim modelines (`vim: set ft=ruby:`) in the first and last five
+/// lines, and emacs modelines (`-*- mode: ruby -*-`) in the first two.
+fn by_modeline<'s>(syntax_set: &'s SyntaxSet, text: &str) -> Option<&'s SyntaxReference> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let head = lines.iter().take(5);
+    let tail = lines.iter().skip(lines.len().saturating_sub(5).max(5));
+
+    let emacs = lines.iter().take(2).find_map(|line| emacs_mode(line));
+    let vim = head.chain(tail).find_map(|line| vim_filetype(line));
+    emacs
+        .into_iter()
+        .chain(vim)
+        .find_map(|token| syntax_set.find_syntax_by_token(token))
+}
+
+fn emacs_mode(line: &str) -> Option<&str> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let body = line[start..end].trim();
+    if !body.contains(':') {
+        return Some(body).filter(|mode| !mode.is_empty());
+    }
+    body.split(';').find_map(|pair| {
+        let (key, value) = pair.split_at(pair.find(':')?);
+        if key.trim().eq_ignore_ascii_case("mode") {
+            Some(value[1..].trim())
+        } else {
+            None
+        }
+    })
+}
+
+fn vim_filetype(line: &str) -> Option<&str> {
+    // Markers only count at the start of a word, so that `envi:` is not `vi:`.
+    let start = ["vim:", "vi:", "ex:"]
+        .iter()
+        .filter_map(|marker| {
+            line.match_indices(marker)
+                .find(|(idx, _)| *idx == 0 || line[..*idx].ends_with(char::is_whitespace))
+                .map(|(idx, _)| idx + marker.len())
+        })
+        .min()?;
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|setting| {
+            let (key, value) = setting.split_at(setting.find('=')?);
+            match key {
+                "ft" | "filetype" | "syn" | "syntax" => Some(&value[1..]),
+                _ => None,
+            }
+        })
+        .filter(|value| !value.is_empty())
+}
+
+/// Detects the language of some text.
+///
+/// # BEAM Arguments
+///
+/// - `text`: The text whose language is sought.
+/// - `path`: The path of the file the text came from, or `nil` if there is no
+///   such file.
+///
+/// # Returns
+///
+/// `{:ok, lang, how}`, where `lang` is the name of the detected language and
+/// `how` is one of `:modeline`, `:filename`, `:extension`, `:shebang`, or
+/// `:first_line`. If no language could be detected, this returns
+/// `{:error, :undetected_lang, details}`, with the path as the value.
+pub fn detect_lang<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let text: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+    let path: Option<&'env str> = args.get(1).ok_or(NifError::BadArg)?.decode()?;
+
+    let syntaxes = SYNTAXES.snapshot();
+    match detect(syntaxes.catalog(), path, text) {
+        Some((syntax, how)) => Ok((NifStatus::Ok, syntax.name.as_str(), how).encode(env)),
+        None => fail(env, undetected(env, path)),
+    }
+}
+
+/// A language name that the library does not know, with the names and file
+/// extensions closest to it as suggestions.
+fn unknown_lang<'env>(env: Env<'env>, syntax_set: &SyntaxSet, token: &str) -> Failure<'env> {
+    let known = syntax_set.syntaxes().iter().flat_map(|syntax| {
+        std::iter::once(syntax.name.as_str())
+            .chain(syntax.file_extensions.iter().map(String::as_str))
+    });
+    Failure::new(
+        ErrorKind::UnknownLang,
+        format!("unknown language: {}", token),
+    )
+    .value(token.encode(env))
+    .suggest(token, known)
+}
+
+/// Text whose language could not be detected.
+fn undetected<'env>(env: Env<'env>, path: Option<&str>) -> Failure<'env> {
+    let message = match path {
+        Some(path) => format!("could not detect the language of {}", path),
+        None => "could not detect the language of the text".to_owned(),
+    };
+    Failure::new(ErrorKind::UndetectedLang, message).value(path.encode(env))
+}
@@ -0,0 +1,155 @@
+//! Writing the whole registry to a binary, and restoring it.
+//!
+//! A dump holds the compiled languages and the themes exactly as the library
+//! has them, including everything added since it started, so that restoring
+//! one compiles nothing. Dumps begin with a line naming the version of the
+//! library that wrote them, followed by the compressed registry; since its
+//! layout follows the library's own types, a dump is only read by the version
+//! that wrote it.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use rustler::{Binary, Encoder, Env, Error as NifError, NifResult, OwnedBinary, Term};
+
+use syntect::highlighting::Theme;
+
+use crate::{
+    errors::Failure, syntaxes::Syntaxes, ErrorKind, NifStatus, Themes, SYNTAXES, THEME_SET,
+};
+
+/// The line at the start of every dump.
+const VERSION: &str = concat!("crayons ", env!("CARGO_PKG_VERSION"), "\n");
+
+/// Why a dump could not be read.
+#[derive(Debug)]
+pub enum DumpError {
+    /// The binary does not start like a dump.
+    NotADump,
+    /// The dump was written by another version of the library.
+    Version(String),
+    /// The dump is damaged, or is not a dump at all.
+    Corrupt(bincode::Error),
+}
+
+/// Writes a set of languages and themes to a compressed binary.
+pub fn dump(syntaxes: &Syntaxes, themes: &Themes) -> bincode::Result<Vec<u8>> {
+    let mut out = ZlibEncoder::new(VERSION.as_bytes().to_vec(), Compression::best());
+    syntaxes.dump(&mut out)?;
+    let themes = themes
+        .iter()
+        .map(|(name, theme)| (name, &**theme))
+        .collect::<BTreeMap<_, _>>();
+    bincode::serialize_into(&mut out, &themes)?;
+    Ok(out.finish()?)
+}
+
+/// Reads a binary written by [`dump`]. The version line is checked before
+/// anything else is read.
+pub fn restore(dump: &[u8]) -> Result<(Syntaxes, Themes), DumpError> {
+    let line_end = dump
+        .iter()
+        .take(64)
+        .position(|&byte| byte == b'\n')
+        .filter(|_| dump.starts_with(b"crayons "))
+        .ok_or(DumpError::NotADump)?;
+    let (version, body) = dump.split_at(line_end + 1);
+    if version != VERSION.as_bytes() {
+        let version = String::from_utf8_lossy(&version[..line_end]).into_owned();
+        return Err(DumpError::Version(version));
+    }
+    let mut input = ZlibDecoder::new(body);
+    let syntaxes = Syntaxes::restore(&mut input)?;
+    let themes: BTreeMap<String, Theme> = bincode::deserialize_from(&mut input)?;
+    let themes = themes
+        .into_iter()
+        .map(|(name, theme)| (name, Arc::new(theme)))
+        .collect();
+    Ok((syntaxes, themes))
+}
+
+/// Dumps every language and theme currently in the library.
+///
+/// # Returns
+///
+/// `{:ok, dump}`, where `dump` is a binary that [`load_registry`] accepts. If
+/// the registry cannot be written, this returns `{:error, :dump_failed,
+/// details}`, and if there is no memory for the binary,
+/// `{:error, :out_of_memory, details}`.
+///
+/// # Blocking
+///
+/// This works from a snapshot of the library, and waits for nothing.
+pub fn dump_registry<'env>(env: Env<'env>, _args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let bytes = match dump(&SYNTAXES.snapshot(), &THEME_SET.snapshot()) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            let message = format!("the registry could not be dumped: {}", err);
+            return Ok(Failure::new(ErrorKind::DumpFailed, message).encode(env));
+        }
+    };
+    let mut binary = match OwnedBinary::new(bytes.len()) {
+        Some(binary) => binary,
+        None => {
+            let message = format!("no memory for a dump of {} bytes", bytes.len());
+            return Ok(Failure::new(ErrorKind::OutOfMemory, message).encode(env));
+        }
+    };
+    binary.as_mut_slice().copy_from_slice(&bytes);
+    Ok((NifStatus::Ok, binary.release(env)).encode(env))
+}
+
+/// Replaces every language and theme in the library with those in a dump.
+///
+/// # BEAM Arguments
+///
+/// - `dump`: A binary produced by [`dump_registry`].
+///
+/// # Returns
+///
+/// `:ok`, or `{:error, :invalid_dump, details}` if the dump is damaged or was
+/// written by another version of the library, in which case the library is
+/// left as it was.
+///
+/// # Blocking
+///
+/// Like [`reset_registry`](crate::reset_registry), this swaps the new sets in
+/// whole, and calls that are already running keep the ones they started with.
+pub fn load_registry<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let dump: Binary<'env> = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+
+    match restore(dump.as_slice()) {
+        Ok((syntaxes, themes)) => {
+            SYNTAXES.replace(syntaxes);
+            THEME_SET.replace(themes);
+            Ok(NifStatus::Ok.encode(env))
+        }
+        Err(err) => Ok(Failure::new(ErrorKind::InvalidDump, err.to_string()).encode(env)),
+    }
+}
+
+impl From<bincode::Error> for DumpError {
+    fn from(err: bincode::Error) -> Self {
+        Self::Corrupt(err)
+    }
+}
+
+impl Display for DumpError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::NotADump => write!(fmt, "not a registry dump"),
+            Self::Version(version) => write!(
+                fmt,
+                "the dump was written by {}, but this is {}",
+                version,
+                VERSION.trim_end()
+            ),
+            Self::Corrupt(err) => write!(fmt, "invalid registry dump: {}", err),
+        }
+    }
+}
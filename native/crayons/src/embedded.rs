@@ -0,0 +1,36 @@
+//! The languages and themes that the library starts with, which the build
+//! script compiles ahead of time.
+//!
+//! These are `syntect`'s defaults, the bundled grammars enabled by crate
+//! features, and the grammars and themes embedded from `CRAYONS_EMBED_DIR`.
+//! Loading them reads the compiled sets back, and compiles nothing.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use flate2::read::ZlibDecoder;
+
+use syntect::highlighting::Theme;
+
+use crate::{syntaxes::Syntaxes, Themes};
+
+/// The languages, as [`Syntaxes::restore`] reads them.
+static SYNTAXES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/syntaxes.bin"));
+
+/// The themes, by name.
+static THEMES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/themes.bin"));
+
+/// Loads the languages that the library starts with. The bundled and embedded
+/// grammars count as added, so they can be removed or replaced.
+pub fn default_syntaxes() -> Syntaxes {
+    Syntaxes::restore(ZlibDecoder::new(SYNTAXES)).expect("compiled by the build script")
+}
+
+/// Loads the themes that the library starts with.
+pub fn default_themes() -> Themes {
+    let themes: BTreeMap<String, Theme> =
+        bincode::deserialize_from(ZlibDecoder::new(THEMES)).expect("compiled by the build script");
+    themes
+        .into_iter()
+        .map(|(name, theme)| (name, Arc::new(theme)))
+        .collect()
+}
@@ -0,0 +1,177 @@
+//! Failures as the BEAM sees them.
+//!
+//! Every NIF reports failure as `{:error, kind, details}`, where `kind` is an
+//! [`ErrorKind`] atom and `details` is a map with at least these keys:
+//!
+//! - `value`: the argument at fault, such as the name of an unknown theme, or
+//!   `nil` when no one argument is.
+//! - `message`: a description of the failure, for people.
+//! - `suggestions`: for an unknown name, the known names closest to it, best
+//!   first, for "did you mean" hints. Otherwise, this is empty.
+//!
+//! Some kinds add entries of their own, such as the per-grammar `errors` of a
+//! batch that failed.
+//!
+//! The one exception is an argument or option of the wrong type, such as
+//! `format: 3`. That is a mistake in the calling code rather than a failure to
+//! handle, so it raises `ArgumentError`, as it does for any NIF.
+
+use rustler::{types::map::map_new, Atom, Encoder, Env, Term};
+
+use crate::{atoms, ErrorKind, NifStatus, Themes};
+
+/// How many suggestions an error offers at most.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A failure to report to the BEAM.
+pub struct Failure<'env> {
+    kind: ErrorKind,
+    message: String,
+    value: Option<Term<'env>>,
+    suggestions: Vec<String>,
+    extra: Vec<(Atom, Term<'env>)>,
+}
+
+impl<'env> Failure<'env> {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            value: None,
+            suggestions: vec![],
+            extra: vec![],
+        }
+    }
+
+    /// Names the argument at fault.
+    pub fn value(mut self, value: Term<'env>) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    /// Suggests the names in `known` that are closest to `unknown`.
+    pub fn suggest<'a>(mut self, unknown: &str, known: impl IntoIterator<Item = &'a str>) -> Self {
+        self.suggestions = suggestions(unknown, known);
+        self
+    }
+
+    /// Adds an entry of this kind's own to the details.
+    pub fn with(mut self, key: Atom, value: Term<'env>) -> Self {
+        self.extra.push((key, value));
+        self
+    }
+
+    /// A theme name that the library does not know.
+    pub fn unknown_theme(env: Env<'env>, theme: &str, theme_set: &Themes) -> Self {
+        Self::new(ErrorKind::UnknownTheme, format!("unknown theme: {}", theme))
+            .value(theme.encode(env))
+            .suggest(theme, theme_set.keys().map(String::as_str))
+    }
+
+    /// A language name that is already taken.
+    pub fn lang_conflict(env: Env<'env>, name: &str) -> Self {
+        Self::new(
+            ErrorKind::LangConflict,
+            format!("a language named {} is already loaded", name),
+        )
+        .value(name.encode(env))
+    }
+
+    /// A `class_prefix` that cannot be written into HTML and CSS as it is.
+    pub fn invalid_prefix(env: Env<'env>, prefix: &str) -> Self {
+        Self::new(
+            ErrorKind::InvalidOption,
+            format!(
+                "invalid class prefix {:?}: use letters, digits, `_`, and `-`, \
+                 not starting with a digit",
+                prefix
+            ),
+        )
+        .value(prefix.encode(env))
+        .with(atoms::option(), atoms::class_prefix().encode(env))
+    }
+
+    /// Text pushed to a stream that was already finished.
+    pub fn stream_finished() -> Self {
+        Self::new(ErrorKind::StreamFinished, "the stream is already finished")
+    }
+
+    /// A stream that panicked partway through earlier text, and whose state
+    /// can no longer be trusted.
+    pub fn stream_poisoned() -> Self {
+        Self::new(
+            ErrorKind::StreamPoisoned,
+            "the stream failed on earlier text and cannot be used",
+        )
+    }
+
+    /// Builds `{:error, kind, details}`.
+    pub fn encode(self, env: Env<'env>) -> Term<'env> {
+        let entries = [
+            (atoms::value(), self.value.encode(env)),
+            (atoms::message(), self.message.encode(env)),
+            (atoms::suggestions(), self.suggestions.encode(env)),
+        ];
+        let details =
+            entries
+                .iter()
+                .copied()
+                .chain(self.extra)
+                .fold(map_new(env), |map, (key, value)| {
+                    map.map_put(key.encode(env), value)
+                        .unwrap_or_else(|_| unreachable!("details are always a map"))
+                });
+        (NifStatus::Error, self.kind, details).encode(env)
+    }
+}
+
+/// Finds the known names closest to an unknown one, ignoring case: those a few
+/// edits away from it, and those that contain it. The closest come first.
+pub fn suggestions<'a>(unknown: &str, known: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let unknown = unknown.to_lowercase();
+    let max_distance = (unknown.chars().count() / 3).max(1);
+    let mut close = known
+        .into_iter()
+        .filter_map(|name| {
+            let lower = name.to_lowercase();
+            let distance = distance(&unknown, &lower);
+            let contains = unknown.len() >= 3 && lower.contains(&unknown);
+            if distance <= max_distance || contains {
+                Some((distance, name))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    close.sort_unstable();
+    close.dedup_by_key(|(_, name)| *name);
+    close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| name.to_owned())
+        .collect()
+}
+
+/// Counts the insertions, deletions, substitutions, and swaps of adjacent
+/// characters that turn one string into another.
+fn distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    // Three rows of the table: two rows back, the previous row, and this one.
+    let mut before = vec![0; b.len() + 1];
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut row = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            row[j] = (prev[j] + 1).min(row[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut prev);
+        std::mem::swap(&mut prev, &mut row);
+    }
+    prev[b.len()]
+}
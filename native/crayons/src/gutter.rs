@@ -0,0 +1,134 @@
+//! Line numbers, rendered in a gutter before each line.
+//!
+//! In HTML, each number is a link to its own line, inside an element that
+//! cannot be selected, so that copying a snippet copies only its code. In a
+//! terminal, the numbers form a dimmed column in the theme's gutter colors.
+
+use std::fmt::Write;
+
+use rustler::{Env, NifResult, Term};
+
+use syntect::highlighting::{Color, Theme};
+
+use crate::{
+    atoms, html, opt,
+    terminal::{Depth, Layer},
+};
+
+/// How the gutter is laid out.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Gutter {
+    /// The number of the first line.
+    pub start: usize,
+    /// The minimum width of a number, in columns. Shorter numbers are padded
+    /// on the left with spaces.
+    pub width: usize,
+    /// Text placed between the number and the line.
+    pub separator: String,
+    /// The prefix of each line's HTML `id`, which is followed by its number.
+    pub anchor: String,
+}
+
+impl Gutter {
+    /// Reads the gutter options, producing `None` unless `line_numbers:` is
+    /// set.
+    pub fn decode<'env>(env: Env<'env>, opts: Term<'env>) -> NifResult<Option<Self>> {
+        if !opt(env, opts, atoms::line_numbers())?.unwrap_or(false) {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            start: opt(env, opts, atoms::line_start())?.unwrap_or(1),
+            width: opt(env, opts, atoms::line_padding())?.unwrap_or(0),
+            separator: opt(env, opts, atoms::line_separator())?.unwrap_or_else(|| " ".into()),
+            anchor: opt(env, opts, atoms::line_anchor())?.unwrap_or_else(|| "L".into()),
+        }))
+    }
+
+    /// Widens the number column to fit every number in a text of `lines`
+    /// lines, so that the column does not shift partway through.
+    pub fn fit(&mut self, lines: usize) {
+        let last = self.start + lines.saturating_sub(1);
+        self.width = self.width.max(last.to_string().len());
+    }
+
+    /// The number of the line at `index`, counting from zero.
+    pub fn number(&self, index: usize) -> usize {
+        self.start + index
+    }
+
+    /// Appends the gutter for a line of inline-styled HTML.
+    pub fn html(&self, out: &mut String, number: usize, theme: &Theme) {
+        out.push_str("<span id=\"");
+        html::escape_into(out, &self.anchor);
+        write!(
+            out,
+            "{}\" style=\"user-select: none; -webkit-user-select: none;",
+            number
+        )
+        .unwrap();
+        if let Some(fg) = foreground(theme) {
+            write!(out, " color: {};", html::css_color(fg)).unwrap();
+        }
+        if let Some(bg) = theme.settings.gutter {
+            write!(out, " background-color: {};", html::css_color(bg)).unwrap();
+        }
+        out.push_str("\"><a href=\"#");
+        html::escape_into(out, &self.anchor);
+        write!(
+            out,
+            "{}\" style=\"color: inherit; text-decoration: none;\">{:>width$}</a>",
+            number,
+            number,
+            width = self.width,
+        )
+        .unwrap();
+        html::escape_into(out, &self.separator);
+        out.push_str("</span>");
+    }
+
+    /// Appends the gutter for a line of classed HTML. The stylesheet from
+    /// [`html::css_for_theme`] styles it.
+    pub fn html_classed(&self, out: &mut String, number: usize, prefix: &str) {
+        write!(out, "<span class=\"{}gutter\" id=\"", prefix).unwrap();
+        html::escape_into(out, &self.anchor);
+        write!(out, "{}\"><a href=\"#", number).unwrap();
+        html::escape_into(out, &self.anchor);
+        write!(
+            out,
+            "{}\">{:>width$}</a>",
+            number,
+            number,
+            width = self.width
+        )
+        .unwrap();
+        html::escape_into(out, &self.separator);
+        out.push_str("</span>");
+    }
+
+    /// Appends the gutter for a line of terminal text. The terminal's
+    /// attributes are reset on either side of it, so the gutter neither
+    /// inherits the previous line's colors nor leaks its own into the line.
+    pub fn terminal(&self, out: &mut String, number: usize, theme: &Theme, depth: Depth) {
+        out.push_str("\x1b[0;2");
+        let colors = [
+            (foreground(theme), Layer::Foreground),
+            (theme.settings.gutter, Layer::Background),
+        ];
+        for (color, layer) in &colors {
+            if let Some(sgr) = color.and_then(|color| depth.sgr(color, *layer)) {
+                write!(out, ";{}", sgr).unwrap();
+            }
+        }
+        write!(out, "m{:>width$}", number, width = self.width).unwrap();
+        out.push_str(&self.separator);
+        out.push_str("\x1b[0m");
+    }
+}
+
+/// The color of the numbers in a theme's gutter.
+pub fn foreground(theme: &Theme) -> Option<Color> {
+    theme
+        .settings
+        .gutter_foreground
+        .or(theme.settings.foreground)
+}
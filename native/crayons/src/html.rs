@@ -0,0 +1,311 @@
+//! HTML rendering that marks up text with CSS classes instead of inline styles.
+//!
+//! Each scope pushed by the parser opens a `<span>` whose classes are the atoms
+//! of that scope, so `entity.name.function.rust` becomes
+//! `class="entity name function rust"`. A prefix can be prepended to every
+//! class name in order to avoid collisions with a page's own stylesheet; it is
+//! written into attributes and selectors as it is, so it must pass
+//! [`valid_prefix`]. Atoms are written as class names too, so any character in
+//! them other than an ASCII letter, digit, `_`, or `-` becomes `_`, and `c++`
+//! becomes `c__`. [`css_for_theme`] renders the stylesheet that matches this
+//! markup for any loaded theme.
+
+use std::fmt::Write;
+
+use syntect::{
+    highlighting::{Color, FontStyle, Theme},
+    parsing::{BasicScopeStackOp, Scope, ScopeStack, ScopeStackOp},
+};
+
+use crate::{
+    gutter,
+    marks::{LineMarks, Tints},
+};
+
+/// How much a line is dimmed when other lines are focused.
+const DIMMED_OPACITY: &str = "0.5";
+
+/// Checks that a class prefix is safe to write into `class` attributes and CSS
+/// selectors unescaped: it must be empty, or start with a letter, `_`, or `-`
+/// and continue with letters, digits, `_`, or `-`.
+pub fn valid_prefix(prefix: &str) -> bool {
+    let mut chars = prefix.chars();
+    match chars.next() {
+        None => true,
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_' || first == '-')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        }
+    }
+}
+
+/// Appends the opening `<pre>` tag of a classed snippet.
+pub fn start_classed_snippet(out: &mut String, prefix: &str) {
+    writeln!(out, "<pre class=\"{}code\">", prefix).unwrap();
+}
+
+/// Appends the closing tags of a classed snippet, including any spans that are
+/// still open because the parser did not pop every scope it pushed.
+pub fn finish_classed_snippet(out: &mut String, open: usize) {
+    close_spans(out, open);
+    out.push_str("</pre>\n");
+}
+
+/// Appends closing tags for the innermost `open` spans.
+pub fn close_spans(out: &mut String, open: usize) {
+    for _ in 0..open {
+        out.push_str("</span>");
+    }
+}
+
+/// Appends opening tags for the innermost `open` scopes of the stack, undoing
+/// [`close_spans`]. This lets other markup, such as a line number, sit between
+/// lines without being nested inside the scopes that continue across them.
+pub fn reopen_spans(out: &mut String, stack: &ScopeStack, prefix: &str, open: usize) {
+    let scopes = stack.as_slice();
+    for scope in &scopes[scopes.len().saturating_sub(open)..] {
+        out.push_str("<span class=\"");
+        scope_classes(out, *scope, prefix);
+        out.push_str("\">");
+    }
+}
+
+/// Appends the opening tag of a marked line with inline styles. The line is
+/// made a block, so that its background reaches across the whole snippet.
+pub fn open_marked_line(out: &mut String, marks: &LineMarks, tint: Option<Color>) {
+    out.push_str("<span style=\"display: inline-block; width: 100%;");
+    if let Some(tint) = tint {
+        write!(out, " background-color: {};", css_color(tint)).unwrap();
+    }
+    if marks.dimmed {
+        write!(out, " opacity: {};", DIMMED_OPACITY).unwrap();
+    }
+    out.push_str("\">");
+}
+
+/// Appends the opening tag of a marked line, with one class per mark. The
+/// stylesheet from [`css_for_theme`] styles them.
+pub fn open_classed_marked_line(out: &mut String, marks: &LineMarks, prefix: &str) {
+    write!(out, "<span class=\"{}line", prefix).unwrap();
+    let classes = [
+        (marks.highlight, "line-highlight"),
+        (marks.inserted, "line-inserted"),
+        (marks.deleted, "line-deleted"),
+        (marks.dimmed, "line-dimmed"),
+    ];
+    for (_, class) in classes.iter().filter(|(set, _)| *set) {
+        write!(out, " {}{}", prefix, class).unwrap();
+    }
+    out.push_str("\">");
+}
+
+/// Appends one line of text, wrapped in spans for each scope operation the
+/// parser emitted on it.
+///
+/// `open` tracks how many spans are currently unclosed; it is carried from one
+/// line to the next since scopes regularly span multiple lines.
+pub fn classed_line(
+    out: &mut String,
+    line: &str,
+    ops: &[(usize, ScopeStackOp)],
+    stack: &mut ScopeStack,
+    prefix: &str,
+    open: &mut usize,
+) {
+    let mut cursor = 0;
+    for (idx, op) in ops {
+        let idx = (*idx).min(line.len());
+        if idx > cursor {
+            escape_into(out, &line[cursor..idx]);
+            cursor = idx;
+        }
+        stack.apply_with_hook(op, |basic, _| match basic {
+            BasicScopeStackOp::Push(scope) => {
+                out.push_str("<span class=\"");
+                scope_classes(out, scope, prefix);
+                out.push_str("\">");
+                *open += 1;
+            }
+            BasicScopeStackOp::Pop => {
+                if *open > 0 {
+                    out.push_str("</span>");
+                    *open -= 1;
+                }
+            }
+        });
+    }
+    escape_into(out, &line[cursor..]);
+}
+
+/// Renders a CSS stylesheet for a theme, matching the markup produced by
+/// [`classed_line`] with the same `prefix`.
+pub fn css_for_theme(theme: &Theme, prefix: &str) -> String {
+    let mut css = String::new();
+    if let Some(name) = theme.name.as_deref() {
+        writeln!(css, "/* {} */", name.replace("*/", "* /")).unwrap();
+    }
+
+    write!(css, ".{}code {{", prefix).unwrap();
+    if let Some(fg) = theme.settings.foreground {
+        write!(css, " color: {};", css_color(fg)).unwrap();
+    }
+    if let Some(bg) = theme.settings.background {
+        write!(css, " background-color: {};", css_color(bg)).unwrap();
+    }
+    css.push_str(" }\n");
+
+    write!(
+        css,
+        ".{p}code .{p}gutter {{ user-select: none; -webkit-user-select: none;",
+        p = prefix
+    )
+    .unwrap();
+    if let Some(fg) = gutter::foreground(theme) {
+        write!(css, " color: {};", css_color(fg)).unwrap();
+    }
+    if let Some(bg) = theme.settings.gutter {
+        write!(css, " background-color: {};", css_color(bg)).unwrap();
+    }
+    css.push_str(" }\n");
+    writeln!(
+        css,
+        ".{p}code .{p}gutter a {{ color: inherit; text-decoration: none; }}",
+        p = prefix
+    )
+    .unwrap();
+
+    // Marked lines. Later rules win, in the same order of precedence that the
+    // other formats use for their tints.
+    let tints = Tints::new(theme);
+    writeln!(
+        css,
+        ".{p}code .{p}line {{ display: inline-block; width: 100%; }}",
+        p = prefix
+    )
+    .unwrap();
+    for (class, tint) in &[
+        ("line-highlight", tints.highlight),
+        ("line-inserted", tints.inserted),
+        ("line-deleted", tints.deleted),
+    ] {
+        writeln!(
+            css,
+            ".{p}code .{p}{} {{ background-color: {}; }}",
+            class,
+            css_color(*tint),
+            p = prefix
+        )
+        .unwrap();
+    }
+    writeln!(
+        css,
+        ".{p}code .{p}line-dimmed {{ opacity: {}; }}",
+        DIMMED_OPACITY,
+        p = prefix
+    )
+    .unwrap();
+
+    for item in &theme.scopes {
+        let selectors = item
+            .scope
+            .selectors
+            .iter()
+            // CSS cannot express "not inside this scope", so rules with
+            // exclusions are dropped rather than over-applied.
+            .filter(|sel| sel.excludes.is_empty())
+            .map(|sel| {
+                let mut out = String::new();
+                write!(out, ".{}code", prefix).unwrap();
+                for scope in sel.path.as_slice() {
+                    out.push(' ');
+                    for atom in scope.build_string().split('.') {
+                        out.push('.');
+                        class_name(&mut out, prefix, atom);
+                    }
+                }
+                out
+            })
+            .collect::<Vec<_>>();
+        if selectors.is_empty() {
+            continue;
+        }
+
+        let style = &item.style;
+        let mut body = String::new();
+        if let Some(fg) = style.foreground {
+            write!(body, " color: {};", css_color(fg)).unwrap();
+        }
+        if let Some(bg) = style.background {
+            write!(body, " background-color: {};", css_color(bg)).unwrap();
+        }
+        if let Some(fs) = style.font_style {
+            if fs.contains(FontStyle::BOLD) {
+                body.push_str(" font-weight: bold;");
+            }
+            if fs.contains(FontStyle::ITALIC) {
+                body.push_str(" font-style: italic;");
+            }
+            if fs.contains(FontStyle::UNDERLINE) {
+                body.push_str(" text-decoration: underline;");
+            }
+        }
+        if body.is_empty() {
+            continue;
+        }
+
+        writeln!(css, "{} {{{} }}", selectors.join(", "), body).unwrap();
+    }
+
+    css
+}
+
+/// Renders a color as a CSS value, only using `rgba()` when it is translucent.
+pub fn css_color(color: Color) -> String {
+    let Color { r, g, b, a } = color;
+    if a == 0xFF {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("rgba({}, {}, {}, {:.3})", r, g, b, a as f32 / 255.0)
+    }
+}
+
+/// Appends text with the HTML-significant characters replaced by entities.
+pub fn escape_into(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Appends the class list for a scope: each of its atoms, prefixed.
+fn scope_classes(out: &mut String, scope: Scope, prefix: &str) {
+    for (idx, atom) in scope.build_string().split('.').enumerate() {
+        if idx > 0 {
+            out.push(' ');
+        }
+        class_name(out, prefix, atom);
+    }
+}
+
+/// Appends the class name for one atom of a scope, which is safe to write into
+/// both a `class` attribute and a CSS selector.
+fn class_name(out: &mut String, prefix: &str, atom: &str) {
+    // A CSS class name cannot start with a digit, or with `-` and a digit.
+    let mut start = prefix.chars().chain(atom.chars());
+    match (start.next(), start.next()) {
+        (Some(c), _) if c.is_ascii_digit() => out.push('_'),
+        (Some('-'), Some(c)) if c.is_ascii_digit() => out.push('_'),
+        _ => {}
+    }
+    out.push_str(prefix);
+    out.extend(atom.chars().map(|c| match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+        _ => '_',
+    }));
+}
@@ -1,26 +1,76 @@
-use rustler::{Atom, Binary, Encoder, Env, Error as NifError, NifResult, Term};
+use rustler::{Atom, Binary, Decoder, Encoder, Env, Error as NifError, NifResult, Term};
 
-use std::{
-    borrow::Cow,
-    fmt::{self, Display, Formatter},
-    io::Cursor,
-    sync::RwLock,
-};
+use std::{collections::BTreeMap, sync::Arc};
 
-use syntect::{
-    easy::HighlightLines,
-    highlighting::ThemeSet,
-    html::highlighted_html_for_string,
-    parsing::{SyntaxDefinition as SyntaxDefn, SyntaxSet},
-    util::as_24_bit_terminal_escaped,
-};
+use syntect::{highlighting::Theme, parsing::SyntaxReference, util::LinesWithEndings};
 
-use tap::{Pipe, Tap};
+use tap::Pipe;
+
+mod base16;
+mod detect;
+mod dump;
+mod embedded;
+mod errors;
+mod gutter;
+mod html;
+mod marks;
+mod packages;
+mod palette;
+mod registry;
+mod render;
+mod scopes;
+mod stream;
+mod syntaxes;
+mod terminal;
+mod textmate;
+mod themes;
+mod tokens;
+
+use crate::{
+    detect::{Lang, UnknownLang},
+    errors::Failure,
+    gutter::Gutter,
+    marks::Marks,
+    registry::Registry,
+    render::{Format, LineState, Painter},
+    syntaxes::{Conflict, Definition, Syntaxes},
+};
 
 mod atoms {
     rustler::rustler_atoms! {
         atom html;
+        atom html_classed;
         atom terminal;
+        atom terminal_256;
+        atom terminal_16;
+        atom terminal_8;
+        atom terminal_mono;
+        atom terminal_ansi;
+        atom tokens;
+
+        atom auto;
+        atom path;
+
+        atom class_prefix;
+        atom option;
+        atom ansi_palette;
+        atom reset_lines;
+        atom line_numbers;
+        atom line_start;
+        atom line_padding;
+        atom line_separator;
+        atom line_anchor;
+        atom mark_lines;
+        atom unknown_lang;
+
+        atom bold;
+        atom italic;
+        atom underline;
+
+        atom value;
+        atom message;
+        atom suggestions;
+        atom errors;
     }
 }
 
@@ -31,30 +81,75 @@ pub enum ErrorKind {
     UnknownFormat,
     InvalidLangDefn,
     InvalidThemeDefn,
+    InvalidOption,
+    StreamFinished,
+    UndetectedLang,
+    InvalidPalette,
+    LangConflict,
+    LangNotFound,
+    InvalidPackage,
+    InvalidDump,
+    DumpFailed,
+    OutOfMemory,
+    StreamPoisoned,
+    UnknownLang,
 }
 
-/// The atoms `:ok` and `:error`
+/// The atoms `:ok` and `:error`, and `:fallback` for results that succeeded
+/// without the requested language.
 #[derive(rustler::NifUnitEnum, Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum NifStatus {
     Ok,
     Error,
+    Fallback,
 }
 
+/// The library's themes, by name.
+///
+/// `syntect`'s `ThemeSet` holds the same map, but cannot be cloned for the
+/// registry. Each theme is shared between copies of the map, so that adding or
+/// removing one only copies pointers.
+pub type Themes = BTreeMap<String, Arc<Theme>>;
+
 lazy_static::lazy_static! {
-    pub static ref SYNTAX_SET: RwLock<Option<SyntaxSet>> = RwLock::new(Some(SyntaxSet::load_defaults_nonewlines()));
-    pub static ref THEME_SET: RwLock<ThemeSet> = RwLock::new(ThemeSet::load_defaults());
+    pub static ref SYNTAXES: Registry<Syntaxes> = Registry::new(embedded::default_syntaxes());
+    pub static ref THEME_SET: Registry<Themes> = Registry::new(embedded::default_themes());
 }
 
 rustler::rustler_export_nifs! {
     "Elixir.Crayons.Native",
     [
-        ("color", 4, color),
-        ("add_lang", 3, add_lang),
+        ("color", 5, color),
+        ("color_dirty", 5, color, rustler::schedule::SchedulerFlags::DirtyCpu),
+        ("scopes", 2, scopes::scopes),
+        ("scopes_dirty", 2, scopes::scopes, rustler::schedule::SchedulerFlags::DirtyCpu),
+        ("css_for_theme", 2, css_for_theme),
+        ("add_lang", 4, add_lang),
+        ("add_langs", 2, add_langs),
         ("add_theme", 2, add_theme),
+        ("add_base16_theme", 2, add_base16_theme),
+        ("load_package", 2, packages::load_package, rustler::schedule::SchedulerFlags::DirtyIo),
+        ("remove_lang", 1, remove_lang),
+        ("remove_theme", 1, remove_theme),
+        ("reset_registry", 0, reset_registry),
+        ("dump_registry", 0, dump::dump_registry, rustler::schedule::SchedulerFlags::DirtyCpu),
+        ("load_registry", 1, dump::load_registry, rustler::schedule::SchedulerFlags::DirtyCpu),
         ("list_langs", 0, list_langs),
         ("list_themes", 0, list_themes),
+        ("detect_lang", 2, detect::detect_lang),
+        ("stream_new", 4, stream::stream_new),
+        ("stream_push", 2, stream::stream_push),
+        ("stream_finish", 1, stream::stream_finish),
     ],
-    None
+    Some(load)
+}
+
+/// Registers the resource types that the NIFs hand to the BEAM.
+// `resource_struct_init!` defines its trait impls inside this function.
+#[allow(non_local_definitions)]
+fn load(env: Env, _info: Term) -> bool {
+    rustler::resource_struct_init!(stream::Stream, env);
+    true
 }
 
 /// NIF entry hook.
@@ -64,109 +159,290 @@ rustler::rustler_export_nifs! {
 /// # BEAM Arguments
 ///
 /// - `lang`: An atom or string naming the source language of the text to be
-///   colored.
-/// - `text`: Some text to be colored. This must be a BEAM binary, and will
-///   cause the function to exit with `{:error, :invalid_text}` if it is not
-///   UTF-8
-/// - `format`: Either `:html` or `:terminal`
-/// - `theme`: One of the theme names defined in [`syntect`][themes]. Currently,
-///   this library does not permit loading additional theme definitions at
-///   runtime.
+///   colored. This may also be `:auto`, to detect the language from the text,
+///   or `{:path, path}`, to detect it from a file path and the text. The
+///   result does not name the detected language; [`detect::detect_lang`]
+///   reports it.
+/// - `text`: Some text to be colored. This must be a UTF-8 binary; anything
+///   else raises `ArgumentError`, as do malformed arguments of every kind.
+/// - `format`: One of `:html`, `:html_classed`, `:terminal`, `:terminal_256`,
+///   `:terminal_16`, `:terminal_8`, `:terminal_mono`, `:terminal_ansi`, or
+///   `:tokens`. The terminal formats differ in how many colors they use:
+///   `:terminal` uses 24-bit color, the next three reduce the theme's colors to
+///   the nearest of xterm's 256 colors or the basic ANSI colors, and
+///   `:terminal_mono` uses no color, only bold, italic, and underline.
+///   `:terminal_ansi` ignores the theme, and instead colors scopes with the
+///   terminal's own palette. `:tokens` produces a list of
+///   [`Token`](tokens::Token) maps for each line of the text, rather than a
+///   string.
+/// - `theme`: The name of a theme known to the library: one of those defined
+///   in [`syntect`][themes], or one added with [`add_theme`] or
+///   [`add_base16_theme`], loaded from a package, or embedded at build time.
+/// - `opts`: A map of further options:
+///   - `class_prefix`: A string prepended to every CSS class emitted by the
+///     `:html_classed` format. Defaults to the empty string. It must be a
+///     valid start of a class name, made of ASCII letters, digits, `_`, and
+///     `-` and not starting with a digit, or the result is
+///     `{:error, :invalid_option, details}`.
+///   - `line_numbers`: Whether to number each line in a gutter. The `:tokens`
+///     format ignores this and the other `line_` options.
+///   - `line_start`: The number of the first line. Defaults to 1.
+///   - `line_padding`: The minimum width of the numbers, in columns. The
+///     numbers are always padded to the width of the last one.
+///   - `line_separator`: Text between the gutter and the line. Defaults to a
+///     space.
+///   - `line_anchor`: The prefix of each line's HTML `id`. Defaults to `"L"`,
+///     so that the first line can be linked as `#L1`.
+///   - `reset_lines`: Whether the terminal formats reset the terminal's
+///     attributes at the end of every line, for pagers such as `less -R` that
+///     show lines out of order. Defaults to `false`.
+///   - `ansi_palette`: A list of `{selector, color}` pairs for `:terminal_ansi`,
+///     where `color` is an atom naming one of the sixteen ANSI colors, such as
+///     `:bright_blue`, or `:default`. These replace the default pairs with the
+///     same selector, and win over the others. An unparsable selector causes
+///     `{:error, :invalid_palette, details}`, with the selector as the value.
+///   - `mark_lines`: A list of `{role, first, last}` tuples, each marking the
+///     lines numbered `first` through `last`, counting from `line_start`. The
+///     roles are `:highlight`, `:inserted`, and `:deleted`, which tint the
+///     lines' background, and `:focus`, which dims every line not focused.
+///   - `unknown_lang`: What to do when `lang` is not known, or cannot be
+///     detected. `:plaintext`, the default, colors the text as plain text;
+///     `:error` fails with `{:error, :unknown_lang, details}`, suggesting the
+///     closest language names and extensions, or with
+///     `{:error, :undetected_lang, details}` for `:auto` and `{:path, path}`;
+///     and `:detect` detects the language from the text, falling back to plain
+///     text if none is detected.
+///
+/// # Returns
+///
+/// `{:ok, colored}` when `lang` names a known language. When it does not, the
+/// text is still made safe for the requested format: HTML formats highlight it
+/// as plain text (escaping it and wrapping it in `<pre>`), and the terminal
+/// format strips control sequences from it. These results are reported as
+/// `{:fallback, colored, lang}`, where `lang` is the name of the language that
+/// was used instead: plain text, or the language detected under
+/// `unknown_lang: :detect`.
+///
+/// An unknown theme or format is `{:error, :unknown_theme, details}` or
+/// `{:error, :unknown_format, details}`, suggesting the closest known names;
+/// see [`errors`] for the details.
+///
+/// # Scheduling
+///
+/// Highlighting takes time proportional to the length of the text, and large
+/// texts easily exceed the time a NIF may hold a normal scheduler. This
+/// function is therefore exported twice: as `color`, which runs on the calling
+/// scheduler, and as `color_dirty`, which runs on a dirty CPU scheduler. The
+/// Elixir wrapper chooses between them.
 ///
 /// # Blocking
 ///
-/// This blocks the system thread when there are calls to [`add_lang`] or
-/// [`add_theme`] ongoing.
+/// This never blocks. It colors the text with a snapshot of the library taken
+/// when it starts, so concurrent calls to [`add_lang`] or [`add_theme`] do not
+/// affect it.
 ///
 /// [themes]: https://docs.rs/syntect/4.5.0/syntect/highlighting/struct.ThemeSet.html#method.load_defaults
 pub fn color<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
     let mut args = args.into_iter();
     let text: &'env str = args.next().ok_or(NifError::BadArg)?.decode()?;
-    let lang_term = args.next().ok_or(NifError::BadArg)?;
-    let lang: Cow<'env, str> = if lang_term.is_atom() {
-        Cow::Owned(lang_term.atom_to_string()?)
-    } else {
-        Cow::Borrowed(lang_term.decode()?)
-    };
+    let lang = Lang::decode(*args.next().ok_or(NifError::BadArg)?)?;
     let fmt: Atom = args.next().ok_or(NifError::BadArg)?.decode()?;
     let theme: &'env str = args.next().ok_or(NifError::BadArg)?.decode()?;
+    let opts = *args.next().ok_or(NifError::BadArg)?;
 
-    // TODO(myrrlyn): Replace blocking reads with yield loops
-    let theme_set = THEME_SET.read().map_err(|_| poison())?;
-    let syntax_set = SYNTAX_SET.read().map_err(|_| poison())?;
+    let theme_set = THEME_SET.snapshot();
+    let syntaxes = SYNTAXES.snapshot();
 
-    let theme = match theme_set.themes.get(theme) {
-        None => return fail(env, UnknownTheme::new(theme)),
+    let theme = match theme_set.get(theme) {
+        None => return fail(env, Failure::unknown_theme(env, theme, &theme_set)),
         Some(t) => t,
     };
-    let syntax_set = syntax_set
-        .as_ref()
-        .expect("a read lock cannot observe an empty syntax set");
-    let syntax = match syntax_set.find_syntax_by_token(&lang) {
-        None => return Ok((NifStatus::Ok, text).encode(env)),
-        Some(s) => s,
+    let policy = opt(env, opts, atoms::unknown_lang())?.unwrap_or(UnknownLang::Plaintext);
+    let resolved = match lang.resolve_or(env, syntaxes.catalog(), text, policy) {
+        Err(failure) => return fail(env, failure),
+        Ok(r) => r,
     };
+    let (syntax, status) = (resolved.syntax, resolved.status);
+    let grammar = syntaxes.grammar(syntax);
 
-    let colored = match fmt {
-        f if f == atoms::html() => highlighted_html_for_string(text, &syntax_set, syntax, theme),
-        f if f == atoms::terminal() => {
-            let mut h = HighlightLines::new(syntax, theme);
-            text.lines()
-                .map(|line| {
-                    let ranges = h.highlight(line, &syntax_set);
-                    as_24_bit_terminal_escaped(&ranges[..], true)
-                })
-                .collect::<Vec<_>>()
-                .join("\n")
-                .tap_mut(|out| out.push_str("\x1b[0m"))
+    if fmt == atoms::tokens() {
+        let tokens = tokens::tokenize(&grammar, theme, text);
+        return Ok(reply(env, status, tokens, syntax));
+    }
+    let format = match Format::decode(env, fmt, opts, &["tokens"])? {
+        Err(failure) => return fail(env, failure),
+        Ok(f) => f,
+    };
+    let palette = if format.uses_palette() {
+        match palette::decode(env, opts)? {
+            Err(failure) => return fail(env, failure),
+            Ok(theme) => Some(theme),
         }
-        _ => return fail(env, ErrorKind::UnknownFormat),
+    } else {
+        None
     };
+    let theme = palette.as_ref().unwrap_or(theme);
+    let mut gutter = Gutter::decode(env, opts)?;
+    let marks = Marks::decode(env, opts)?;
+    if resolved.plain && format.is_terminal() && gutter.is_none() && marks.is_none() {
+        return Ok(reply(env, status, terminal::strip_controls(text), syntax));
+    }
+    if let Some(gutter) = gutter.as_mut() {
+        gutter.fit(text.lines().count());
+    }
+
+    let mut painter = Painter::new(grammar.set, theme, &format)
+        .gutter(gutter.as_ref())
+        .marks(marks.as_ref());
+    if resolved.plain {
+        painter = painter.plain();
+    }
+    let mut state = LineState::new(&grammar, theme);
+    let mut colored = String::with_capacity(text.len());
+    painter.begin(&mut colored);
+    // Line endings are copied through exactly as they appear in the text, so
+    // that stripping the markup or escapes from the output gives back the text.
+    for line in LinesWithEndings::from(text) {
+        let content = line.trim_end_matches(&['\r', '\n'][..]);
+        painter.line(&mut state, &mut colored, content);
+        colored.push_str(&line[content.len()..]);
+    }
+    painter.finish(&mut state, &mut colored);
 
-    Ok((NifStatus::Ok, colored).encode(env))
+    Ok(reply(env, status, colored, syntax))
+}
+
+/// Renders a CSS stylesheet for a theme, for use with the `:html_classed`
+/// format of [`color`].
+///
+/// # BEAM Arguments
+///
+/// - `theme`: One of the theme names known to the library.
+/// - `class_prefix`: The same prefix given to [`color`], so that the selectors
+///   in the stylesheet match the emitted classes.
+///
+/// # Returns
+///
+/// `{:ok, css}`, `{:error, :unknown_theme, details}`, or
+/// `{:error, :invalid_option, details}` if the prefix is not a valid class
+/// name prefix.
+pub fn css_for_theme<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let theme: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+    let class_prefix: &'env str = args.get(1).ok_or(NifError::BadArg)?.decode()?;
+    if !html::valid_prefix(class_prefix) {
+        return fail(env, Failure::invalid_prefix(env, class_prefix));
+    }
+
+    let theme_set = THEME_SET.snapshot();
+    match theme_set.get(theme) {
+        None => fail(env, Failure::unknown_theme(env, theme, &theme_set)),
+        Some(t) => Ok((NifStatus::Ok, html::css_for_theme(t, class_prefix)).encode(env)),
+    }
 }
 
 /// Adds a syntax definition to the library.
 ///
 /// # BEAM Arguments
 ///
-/// - `contents`: The contents of a `.sublime-syntax` or `.tmLanguage` file,
-///   which defines a text shape.
+/// - `contents`: The contents of a `.sublime-syntax` file, or of a TextMate
+///   grammar as either a `.tmLanguage` property list or a `.tmLanguage.json`
+///   file, which defines a text shape. TextMate grammars are converted to
+///   `.sublime-syntax` grammars, and constructs that cannot be converted are
+///   listed in the error message along with where they occur.
 /// - `name`: A name (can be `nil`) of the language being defined.
 /// - `incl_newline`: A bool indicating whether the grammar expects newlines in
-///   text parsed by it or not.
+///   text parsed by it or not. The grammar is compiled both ways, and this
+///   chooses which compilation the language is parsed with. TextMate grammars
+///   always expect newlines, so this is ignored for them.
+/// - `on_conflict`: What to do if a language of the same name is already
+///   loaded: `:replace` it, fail with `:lang_conflict`, or `:keep_both`, in
+///   which case lookups find the new one.
+///
+/// # Returns
+///
+/// `{:ok, name}`, or `{:error, :invalid_lang_defn, details}` with the reason
+/// the grammar could not be read as the message, or
+/// `{:error, :lang_conflict, details}` with the taken name as the value.
 ///
 /// # Blocking
 ///
-/// This blocks the system thread when there are calls to [`color`] or other
-/// calls to itself ongoing.
+/// This waits for other calls to itself to finish, but never for calls to
+/// [`color`]. The syntax sets are rebuilt off to the side and swapped in once
+/// they are complete.
 pub fn add_lang<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
     let syntax_content: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
     let name: Option<&'env str> = args.get(1).ok_or(NifError::BadArg)?.decode()?;
     let incl_newline: bool = args.get(2).ok_or(NifError::BadArg)?.decode()?;
+    let on_conflict: Conflict = args.get(3).ok_or(NifError::BadArg)?.decode()?;
 
-    Ok(
-        match SyntaxDefn::load_from_str(syntax_content, incl_newline, name) {
-            Ok(syntax) => {
-                let mut syntax_set = SYNTAX_SET.write().map_err(|_| poison())?;
-                let mut builder = syntax_set
-                    .take()
-                    // Should never run
-                    .unwrap_or_else(SyntaxSet::load_defaults_nonewlines)
-                    .into_builder();
-                let name = syntax.name.encode(env);
-                builder.add(syntax);
-                *syntax_set = Some(builder.build());
-                (NifStatus::Ok, name).encode(env)
+    Ok(match Definition::load(syntax_content, incl_newline, name) {
+        Ok(definition) => {
+            let name = definition.name().encode(env);
+            match SYNTAXES.modify(|syntaxes| syntaxes.add(Some(definition), on_conflict)) {
+                Ok(()) => (NifStatus::Ok, name).encode(env),
+                Err(taken) => Failure::lang_conflict(env, &taken).encode(env),
             }
-            Err(e) => (
-                NifStatus::Error,
-                ErrorKind::InvalidLangDefn,
-                format!("{}", e),
-            )
-                .encode(env),
-        },
-    )
+        }
+        Err(e) => Failure::new(ErrorKind::InvalidLangDefn, format!("{}", e))
+            .value(name.encode(env))
+            .encode(env),
+    })
+}
+
+/// Adds several syntax definitions to the library at once. Either all of them
+/// are added or none are.
+///
+/// # BEAM Arguments
+///
+/// - `definitions`: A list of `{contents, name, incl_newline}` tuples, each
+///   read as by [`add_lang`].
+/// - `on_conflict`: What to do if a language's name is already loaded, or is
+///   used by an earlier definition in the list, as by [`add_lang`].
+///
+/// # Returns
+///
+/// `{:ok, names}` in the order of the definitions. If any of them cannot be
+/// read, `{:error, :invalid_lang_defn, details}`, where the details also hold
+/// `errors`, pairing the zero-based index of each one that failed with its
+/// message. If a name conflicts, `{:error, :lang_conflict, details}` with the
+/// taken name as the value.
+///
+/// # Blocking
+///
+/// Every definition is read before the library is touched, and the syntax sets
+/// are rebuilt only once, so this is much cheaper than adding the languages one
+/// by one. Like [`add_lang`], it waits only for other calls that add languages.
+pub fn add_langs<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let sources: Vec<(&'env str, Option<&'env str>, bool)> =
+        args.get(0).ok_or(NifError::BadArg)?.decode()?;
+    let on_conflict: Conflict = args.get(1).ok_or(NifError::BadArg)?.decode()?;
+
+    let mut definitions = Vec::with_capacity(sources.len());
+    let mut errors = vec![];
+    for (idx, (contents, name, incl_newline)) in sources.into_iter().enumerate() {
+        match Definition::load(contents, incl_newline, name) {
+            Ok(definition) => definitions.push(definition),
+            Err(e) => errors.push((idx, format!("{}", e))),
+        }
+    }
+    if !errors.is_empty() {
+        let message = format!(
+            "{} of {} grammars could not be read",
+            errors.len(),
+            errors.len() + definitions.len()
+        );
+        let failure = Failure::new(ErrorKind::InvalidLangDefn, message);
+        return fail(env, failure.with(atoms::errors(), errors.encode(env)));
+    }
+
+    let names = definitions
+        .iter()
+        .map(|def| def.name().to_owned())
+        .collect::<Vec<_>>();
+    match SYNTAXES.modify(|syntaxes| syntaxes.add(definitions, on_conflict)) {
+        Ok(()) => Ok((NifStatus::Ok, names).encode(env)),
+        Err(taken) => fail(env, Failure::lang_conflict(env, &taken)),
+    }
 }
 
 /// Adds a theme definition to the library. If the named theme already existed,
@@ -174,44 +450,137 @@ pub fn add_lang<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'en
 ///
 /// # BEAM Arguments
 ///
-/// - `contents`: A `Binary` containing the contents of a `.tmTheme` file.
+/// - `contents`: A `Binary` containing the contents of a `.tmTheme` file, a VS
+///   Code color theme, or a `.sublime-color-scheme` file. VS Code's editor
+///   colors, such as `editor.lineHighlightBackground`, and a color scheme's
+///   `globals` become the theme's settings.
 /// - `name`: The name of the theme, which can be used in calls to [`color`].
 ///
 /// # Blocking
 ///
-/// This blocks the system thread when there are calls to [`color`] or other
-/// calls to itself ongoing.
+/// This waits for other calls to itself to finish, but never for calls to
+/// [`color`].
 pub fn add_theme<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
     let theme_content: Binary<'env> = args.get(0).ok_or(NifError::BadArg)?.decode()?;
     let name: &'env str = args.get(1).ok_or(NifError::BadArg)?.decode()?;
 
-    let mut cursor = Cursor::new(theme_content.as_slice());
-    Ok(match ThemeSet::load_from_reader(&mut cursor) {
+    Ok(match themes::load(theme_content.as_slice()) {
         Ok(theme) => {
-            let mut theme_set = THEME_SET.write().map_err(|_| poison())?;
-            theme_set.themes.insert(name.to_owned(), theme);
+            THEME_SET.modify(|theme_set| theme_set.insert(name.to_owned(), Arc::new(theme)));
             (NifStatus::Ok, name).encode(env)
         }
-        Err(e) => (
-            NifStatus::Error,
-            ErrorKind::InvalidThemeDefn,
-            format!("{}", e),
-        )
+        Err(e) => Failure::new(ErrorKind::InvalidThemeDefn, format!("{}", e))
+            .value(name.encode(env))
             .encode(env),
     })
 }
 
+/// Generates a theme from a Base16 or Base24 color scheme and adds it to the
+/// library. If the named theme already existed, the old theme definition is
+/// discarded.
+///
+/// # BEAM Arguments
+///
+/// - `contents`: The YAML of a Base16 or Base24 scheme, in either the flat
+///   form with `base00` through `base0F` at the top level, or the newer form
+///   with them under `palette`.
+/// - `name`: The name of the theme, which can be used in calls to [`color`].
+///
+/// # Blocking
+///
+/// This waits for other calls to itself to finish, but never for calls to
+/// [`color`].
+pub fn add_base16_theme<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let scheme: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+    let name: &'env str = args.get(1).ok_or(NifError::BadArg)?.decode()?;
+
+    Ok(match base16::theme(scheme) {
+        Ok(theme) => {
+            THEME_SET.modify(|theme_set| theme_set.insert(name.to_owned(), Arc::new(theme)));
+            (NifStatus::Ok, name).encode(env)
+        }
+        Err(e) => Failure::new(ErrorKind::InvalidThemeDefn, format!("{}", e))
+            .value(name.encode(env))
+            .encode(env),
+    })
+}
+
+/// Removes a language that was added to the library, including the grammars
+/// bundled with it. If several languages have the name, they are all removed.
+/// The syntaxes built into `syntect` cannot be removed, and are not found.
+///
+/// # BEAM Arguments
+///
+/// - `name`: The name of the language, in any case.
+///
+/// # Returns
+///
+/// `{:ok, name}`, with the name as the language spelled it, or
+/// `{:error, :lang_not_found, details}`, suggesting the closest names among
+/// the languages that can be removed.
+///
+/// # Blocking
+///
+/// This waits for other calls that change the library, but never for calls to
+/// [`color`].
+pub fn remove_lang<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let name: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+
+    if let Some(removed) = SYNTAXES.modify(|syntaxes| syntaxes.remove(name)) {
+        return Ok((NifStatus::Ok, removed).encode(env));
+    }
+    let syntaxes = SYNTAXES.snapshot();
+    let message = if syntaxes.contains(name) {
+        format!("{} is built into syntect, and cannot be removed", name)
+    } else {
+        format!("no added language is named {}", name)
+    };
+    let failure = Failure::new(ErrorKind::LangNotFound, message)
+        .value(name.encode(env))
+        .suggest(name, syntaxes.added());
+    fail(env, failure)
+}
+
+/// Removes a theme from the library.
+///
+/// # BEAM Arguments
+///
+/// - `name`: The name of the theme.
+///
+/// # Returns
+///
+/// `{:ok, name}`, or `{:error, :unknown_theme, details}`.
+pub fn remove_theme<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let name: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+
+    match THEME_SET.modify(|theme_set| theme_set.remove(name)) {
+        Some(_) => Ok((NifStatus::Ok, name).encode(env)),
+        None => fail(
+            env,
+            Failure::unknown_theme(env, name, &THEME_SET.snapshot()),
+        ),
+    }
+}
+
+/// Restores the library to the languages and themes that it starts with,
+/// discarding everything that was added or removed since.
+///
+/// Calls that are already running keep the snapshots they started with.
+pub fn reset_registry<'env>(env: Env<'env>, _args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    SYNTAXES.replace(embedded::default_syntaxes());
+    THEME_SET.replace(embedded::default_themes());
+    Ok(NifStatus::Ok.encode(env))
+}
+
 /// Lists all languages currently in the library.
 pub fn list_langs<'env>(env: Env<'env>, _args: &[Term<'env>]) -> NifResult<Term<'env>> {
-    SYNTAX_SET
-        .read()
-        .map_err(|_| poison())?
-        .as_ref()
-        .expect("a read lock can never observe an empty SyntaxSet")
+    SYNTAXES
+        .snapshot()
+        .catalog()
         .syntaxes()
         .into_iter()
         .filter(|syntax| !syntax.hidden)
-		.map(|syntax| syntax.name.to_lowercase())
+        .map(|syntax| syntax.name.to_lowercase())
         .collect::<Vec<_>>()
         .encode(env)
         .pipe(Ok)
@@ -220,9 +589,7 @@ pub fn list_langs<'env>(env: Env<'env>, _args: &[Term<'env>]) -> NifResult<Term<
 /// Lists all themes currently in the library.
 pub fn list_themes<'env>(env: Env<'env>, _args: &[Term<'env>]) -> NifResult<Term<'env>> {
     THEME_SET
-        .read()
-        .map_err(|_| poison())?
-        .themes
+        .snapshot()
         .keys()
         .map(|k| &**k)
         .collect::<Vec<_>>()
@@ -230,33 +597,35 @@ pub fn list_themes<'env>(env: Env<'env>, _args: &[Term<'env>]) -> NifResult<Term
         .pipe(Ok)
 }
 
-fn fail<'env, T: Encoder>(env: Env<'env>, term: T) -> NifResult<Term<'env>> {
-    Ok((NifStatus::Error, term).encode(env))
-}
-
-fn poison() -> NifError {
-    NifError::Atom("library_poisoned")
-}
-
-#[derive(Clone, Debug)]
-pub struct UnknownTheme<'env> {
-    inner: &'env str,
-}
-
-impl<'env> UnknownTheme<'env> {
-    fn new(theme: &'env str) -> Self {
-        Self { inner: theme }
+/// Looks up an optional key in an options map passed to a NIF.
+///
+/// A missing key, or a key set to `nil`, produces `None`; a present key that
+/// cannot be decoded as `T` is a `BadArg`.
+fn opt<'env, T: Decoder<'env>>(
+    env: Env<'env>,
+    opts: Term<'env>,
+    key: Atom,
+) -> NifResult<Option<T>> {
+    match opts.map_get(key.encode(env)) {
+        Err(_) => Ok(None),
+        Ok(term) => term.decode(),
     }
 }
 
-impl Display for UnknownTheme<'_> {
-    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        write!(fmt, "Unknown theme: {}", self.inner)
+/// Encodes a successful result, which also names the language used if it had
+/// to fall back from the one requested.
+fn reply<'env, T: Encoder>(
+    env: Env<'env>,
+    status: NifStatus,
+    value: T,
+    syntax: &SyntaxReference,
+) -> Term<'env> {
+    match status {
+        NifStatus::Fallback => (status, value, syntax.name.as_str()).encode(env),
+        _ => (status, value).encode(env),
     }
 }
 
-impl Encoder for UnknownTheme<'_> {
-    fn encode<'env>(&self, env: Env<'env>) -> Term<'env> {
-        ErrorKind::UnknownTheme.encode(env)
-    }
+fn fail<'env>(env: Env<'env>, failure: Failure<'env>) -> NifResult<Term<'env>> {
+    Ok(failure.encode(env))
 }
@@ -0,0 +1,162 @@
+//! Lines singled out for the reader's attention.
+//!
+//! Ranges of lines can be marked as highlighted, inserted, or deleted, which
+//! tints their background, or as focused, which dims every line that is not.
+//! The tints come from the theme: highlighted lines use its `lineHighlight`
+//! color, and inserted and deleted lines blend its colors for the
+//! `markup.inserted` and `markup.deleted` scopes into its background.
+
+use rustler::{Env, NifResult, Term};
+
+use syntect::{
+    highlighting::{Color, Highlighter, Theme},
+    parsing::Scope,
+};
+
+use crate::{atoms, opt};
+
+/// What a range of lines is marked as.
+#[derive(rustler::NifUnitEnum, Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Role {
+    Highlight,
+    Focus,
+    Inserted,
+    Deleted,
+}
+
+/// The marked ranges of a text.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Marks {
+    /// The number of the first line, which the ranges count from.
+    start: usize,
+    /// Inclusive ranges of line numbers.
+    ranges: Vec<(Role, usize, usize)>,
+    /// Whether any line is focused, which dims the others.
+    focused: bool,
+}
+
+/// The marks that apply to a single line.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LineMarks {
+    pub highlight: bool,
+    pub inserted: bool,
+    pub deleted: bool,
+    pub dimmed: bool,
+}
+
+impl Marks {
+    /// Reads the `mark_lines:` option, a list of `{role, first, last}` tuples,
+    /// producing `None` if no line is marked.
+    pub fn decode<'env>(env: Env<'env>, opts: Term<'env>) -> NifResult<Option<Self>> {
+        let ranges: Vec<(Role, usize, usize)> =
+            opt(env, opts, atoms::mark_lines())?.unwrap_or_default();
+        if ranges.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            start: opt(env, opts, atoms::line_start())?.unwrap_or(1),
+            focused: ranges.iter().any(|(role, _, _)| *role == Role::Focus),
+            ranges,
+        }))
+    }
+
+    /// Gets the marks of the line at `index`, counting from zero.
+    pub fn line(&self, index: usize) -> LineMarks {
+        let number = self.start + index;
+        let has = |want: Role| {
+            self.ranges
+                .iter()
+                .any(|&(role, first, last)| role == want && (first..=last).contains(&number))
+        };
+        LineMarks {
+            highlight: has(Role::Highlight),
+            inserted: has(Role::Inserted),
+            deleted: has(Role::Deleted),
+            dimmed: self.focused && !has(Role::Focus),
+        }
+    }
+}
+
+impl LineMarks {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Picks the background tint of the line. Deletion and insertion say more
+    /// about a line than highlighting does, so they win.
+    pub fn tint(&self, tints: &Tints) -> Option<Color> {
+        if self.deleted {
+            Some(tints.deleted)
+        } else if self.inserted {
+            Some(tints.inserted)
+        } else if self.highlight {
+            Some(tints.highlight)
+        } else {
+            None
+        }
+    }
+}
+
+/// The colors blended into inserted and deleted lines when the theme has none
+/// of its own.
+const INSERTED: Color = Color {
+    r: 0x3f,
+    g: 0xb9,
+    b: 0x50,
+    a: 0xff,
+};
+const DELETED: Color = Color {
+    r: 0xf8,
+    g: 0x51,
+    b: 0x49,
+    a: 0xff,
+};
+
+/// Opaque background colors for marked lines, derived from a theme.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Tints {
+    pub highlight: Color,
+    pub inserted: Color,
+    pub deleted: Color,
+}
+
+impl Tints {
+    pub fn new(theme: &Theme) -> Self {
+        let settings = &theme.settings;
+        let background = settings.background.unwrap_or(Color::WHITE);
+        let foreground = settings.foreground.unwrap_or(Color::BLACK);
+        let highlighter = Highlighter::new(theme);
+        let scope_color = |scope: &str, default: Color| {
+            Scope::new(scope)
+                .ok()
+                .and_then(|scope| highlighter.style_mod_for_stack(&[scope]).foreground)
+                .unwrap_or(default)
+        };
+
+        let highlight = match settings.line_highlight {
+            Some(color) => blend(background, color, color.a),
+            None => blend(background, foreground, 0x20),
+        };
+        let inserted = scope_color("markup.inserted", INSERTED);
+        let deleted = scope_color("markup.deleted", DELETED);
+        Self {
+            highlight,
+            inserted: blend(background, inserted, 0x40),
+            deleted: blend(background, deleted, 0x40),
+        }
+    }
+}
+
+/// Mixes `over` into `under` in the proportion `alpha / 255`.
+fn blend(under: Color, over: Color, alpha: u8) -> Color {
+    let mix = |u: u8, o: u8| {
+        let (u, o, a) = (u as u32, o as u32, alpha as u32);
+        ((o * a + u * (255 - a) + 127) / 255) as u8
+    };
+    Color {
+        r: mix(under.r, over.r),
+        g: mix(under.g, over.g),
+        b: mix(under.b, over.b),
+        a: 0xff,
+    }
+}
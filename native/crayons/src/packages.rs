@@ -0,0 +1,289 @@
+//! Loading whole Sublime Text packages, from a directory or a
+//! `.sublime-package` archive.
+//!
+//! A package is searched for grammars, themes, and preferences by their file
+//! extensions; everything else in it is ignored. A directory is searched
+//! recursively, and any `.sublime-package` archives inside it are opened in
+//! turn, so a directory of vendored packages loads in one call.
+//!
+//! Sublime Text always feeds grammars lines that end in `\n`, so the grammars
+//! of a package are loaded in that mode. Preferences hold editor metadata, such
+//! as comment markers and indentation rules, that does not affect highlighting;
+//! they are collected but not loaded, and reported as skipped.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io::{self, Read, Seek},
+    path::Path,
+    sync::Arc,
+};
+
+use rustler::{Encoder, Env, Error as NifError, NifResult, Term};
+
+use syntect::highlighting::Theme;
+
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    errors::Failure,
+    syntaxes::{Conflict, Definition},
+    themes, ErrorKind, NifStatus, SYNTAXES, THEME_SET,
+};
+
+/// What a file in a package holds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Grammar,
+    Theme,
+    Preferences,
+}
+
+/// A file collected from a package, with its path inside the package.
+struct Entry {
+    path: String,
+    kind: Kind,
+    contents: Vec<u8>,
+}
+
+/// The files of a package, and those that could not be read.
+#[derive(Default)]
+struct Package {
+    entries: Vec<Entry>,
+    failed: Vec<(String, String)>,
+}
+
+/// Why a package could not be opened at all.
+#[derive(Debug)]
+pub enum PackageError {
+    Io(io::Error),
+    Zip(ZipError),
+}
+
+/// What was loaded from a package.
+#[derive(rustler::NifMap, Clone, Debug, Default)]
+pub struct Report {
+    /// The names of the languages that were added.
+    pub langs: Vec<String>,
+    /// The names of the themes that were added.
+    pub themes: Vec<String>,
+    /// The path of each file that could not be loaded, with the reason.
+    pub failed: Vec<(String, String)>,
+    /// The paths of the files that were found but are not used.
+    pub skipped: Vec<String>,
+}
+
+impl Kind {
+    fn of(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        match ext {
+            "sublime-syntax" => Some(Self::Grammar),
+            "tmTheme" | "sublime-color-scheme" => Some(Self::Theme),
+            "tmPreferences" => Some(Self::Preferences),
+            _ => None,
+        }
+    }
+}
+
+impl Package {
+    /// Collects the files of a directory or an archive.
+    fn open(path: &Path) -> Result<Self, PackageError> {
+        let mut package = Self::default();
+        if path.is_dir() {
+            package.walk(path, path)?;
+        } else {
+            package.unzip(File::open(path)?, "")?;
+        }
+        package.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(package)
+    }
+
+    /// Collects the files under a directory. Archives inside it that cannot be
+    /// opened are recorded as failures rather than stopping the search.
+    fn walk(&mut self, root: &Path, dir: &Path) -> Result<(), PackageError> {
+        for child in fs::read_dir(dir)? {
+            let child = child?.path();
+            if child.is_dir() {
+                self.walk(root, &child)?;
+                continue;
+            }
+            let path = child
+                .strip_prefix(root)
+                .unwrap_or(&child)
+                .to_string_lossy()
+                .replace('\\', "/");
+            if path.ends_with(".sublime-package") {
+                let prefix = format!("{}/", path);
+                let opened = File::open(&child)
+                    .map_err(PackageError::Io)
+                    .and_then(|file| self.unzip(file, &prefix));
+                if let Err(err) = opened {
+                    self.failed.push((path, err.to_string()));
+                }
+            } else if let Some(kind) = Kind::of(&path) {
+                match fs::read(&child) {
+                    Ok(contents) => self.entries.push(Entry {
+                        path,
+                        kind,
+                        contents,
+                    }),
+                    Err(err) => self.failed.push((path, err.to_string())),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the files in an archive, naming them under `prefix`.
+    fn unzip(&mut self, archive: impl Read + Seek, prefix: &str) -> Result<(), PackageError> {
+        let mut archive = ZipArchive::new(archive)?;
+        for idx in 0..archive.len() {
+            let mut file = archive.by_index(idx)?;
+            let path = format!("{}{}", prefix, file.name());
+            let kind = match Kind::of(&path) {
+                Some(kind) if !file.is_dir() => kind,
+                _ => continue,
+            };
+            let mut contents = Vec::with_capacity(file.size() as usize);
+            match file.read_to_end(&mut contents) {
+                Ok(_) => self.entries.push(Entry {
+                    path,
+                    kind,
+                    contents,
+                }),
+                Err(err) => self.failed.push((path, err.to_string())),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Entry {
+    /// The file's name without its directory or extension, which names themes
+    /// and grammars that do not name themselves.
+    fn stem(&self) -> &str {
+        let name = self.path.rsplit('/').next().unwrap_or(&self.path);
+        name.rfind('.').map_or(name, |dot| &name[..dot])
+    }
+
+    fn grammar(&self) -> Result<Definition, String> {
+        let source = std::str::from_utf8(&self.contents).map_err(|err| err.to_string())?;
+        Definition::load(source, true, Some(self.stem())).map_err(|err| err.to_string())
+    }
+
+    fn theme(&self) -> Result<Theme, String> {
+        themes::load(&self.contents).map_err(|err| err.to_string())
+    }
+}
+
+/// Loads every grammar and theme in a Sublime Text package.
+///
+/// # BEAM Arguments
+///
+/// - `path`: A directory, which is searched recursively for `.sublime-syntax`,
+///   `.tmTheme`, `.sublime-color-scheme`, and `.tmPreferences` files and for
+///   `.sublime-package` archives, or the path of a `.sublime-package` archive.
+/// - `on_conflict`: What to do if a language's name is already loaded, as by
+///   [`add_lang`](crate::add_lang).
+///
+/// # Returns
+///
+/// `{:ok, report}`, where `report` is a map of the `langs` and `themes` that
+/// were added, the files that `failed` to load as `{path, message}` pairs, and
+/// the files that were `skipped`. Only `.tmPreferences` files are skipped,
+/// since they do not affect highlighting. Paths are relative to the package.
+/// Themes are named after their files, and replace any themes of the same name.
+/// When two files in the package would give the same name, such as a
+/// `.tmTheme` and a `.sublime-color-scheme` of one theme, the first by path is
+/// loaded and the other fails.
+///
+/// If the package cannot be opened, this returns
+/// `{:error, :invalid_package, details}` with the path as the value, and if a
+/// language's name conflicts, `{:error, :lang_conflict, details}`; either way,
+/// nothing is added.
+///
+/// # Blocking
+///
+/// This reads from the filesystem, and runs on a dirty I/O scheduler. The
+/// languages it finds are added all at once, as by
+/// [`add_langs`](crate::add_langs).
+pub fn load_package<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let path: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+    let on_conflict: Conflict = args.get(1).ok_or(NifError::BadArg)?.decode()?;
+
+    let package = match Package::open(Path::new(path)) {
+        Ok(package) => package,
+        Err(err) => {
+            let failure = Failure::new(ErrorKind::InvalidPackage, format!("{}: {}", path, err));
+            return Ok(failure.value(path.encode(env)).encode(env));
+        }
+    };
+
+    let mut report = Report {
+        failed: package.failed,
+        ..Report::default()
+    };
+    let mut definitions = vec![];
+    let mut themes = vec![];
+    // The path each theme was loaded from, by name.
+    let mut theme_paths = HashMap::new();
+    for entry in package.entries {
+        let loaded = match entry.kind {
+            Kind::Grammar => entry.grammar().map(|definition| {
+                report.langs.push(definition.name().to_owned());
+                definitions.push(definition);
+            }),
+            Kind::Theme => match theme_paths.get(entry.stem()) {
+                Some(first) => Err(format!(
+                    "a theme named {} was already loaded from {}",
+                    entry.stem(),
+                    first
+                )),
+                None => entry.theme().map(|theme| {
+                    theme_paths.insert(entry.stem().to_owned(), entry.path.clone());
+                    report.themes.push(entry.stem().to_owned());
+                    themes.push((entry.stem().to_owned(), Arc::new(theme)));
+                }),
+            },
+            Kind::Preferences => {
+                report.skipped.push(entry.path.clone());
+                Ok(())
+            }
+        };
+        if let Err(message) = loaded {
+            report.failed.push((entry.path, message));
+        }
+    }
+
+    if !definitions.is_empty() {
+        if let Err(taken) = SYNTAXES.modify(|syntaxes| syntaxes.add(definitions, on_conflict)) {
+            return Ok(Failure::lang_conflict(env, &taken).encode(env));
+        }
+    }
+    if !themes.is_empty() {
+        THEME_SET.modify(|theme_set| theme_set.extend(themes));
+    }
+    Ok((NifStatus::Ok, report).encode(env))
+}
+
+impl From<io::Error> for PackageError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ZipError> for PackageError {
+    fn from(err: ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+impl Display for PackageError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => Display::fmt(err, fmt),
+            Self::Zip(err) => write!(fmt, "invalid package archive: {}", err),
+        }
+    }
+}
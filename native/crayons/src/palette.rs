@@ -0,0 +1,144 @@
+//! Coloring with the terminal's own palette instead of a theme's colors.
+//!
+//! Rather than reducing a theme's RGB values, this maps categories of scopes
+//! straight to the sixteen named ANSI colors, so that highlighted text takes
+//! on whatever color scheme the reader's terminal uses. The mapping is built
+//! into a [`Theme`] whose colors stand for palette entries (see
+//! [`terminal::palette_color`]), so that it is matched against scopes exactly
+//! as any other theme is.
+
+use std::str::FromStr;
+
+use rustler::{Encoder, Env, NifResult, Term};
+
+use syntect::highlighting::{
+    Color, ScopeSelectors, StyleModifier, Theme, ThemeItem, ThemeSettings,
+};
+
+use crate::{
+    atoms,
+    errors::Failure,
+    marks::Tints,
+    opt,
+    terminal::{self, PALETTE_DEFAULT},
+    ErrorKind,
+};
+
+/// The sixteen named ANSI colors, and the terminal's default color.
+#[derive(rustler::NifUnitEnum, Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum AnsiColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    Default,
+}
+
+impl AnsiColor {
+    fn color(self) -> Color {
+        match self {
+            Self::Default => PALETTE_DEFAULT,
+            color => terminal::palette_color(color as u8),
+        }
+    }
+}
+
+/// The mapping used unless the caller replaces parts of it. Later entries win
+/// over earlier ones that match a scope equally well.
+const DEFAULT_PALETTE: &[(&str, AnsiColor)] = &[
+    ("comment", AnsiColor::BrightBlack),
+    ("string", AnsiColor::Green),
+    ("constant", AnsiColor::Cyan),
+    ("constant.character.escape", AnsiColor::Magenta),
+    ("keyword", AnsiColor::Magenta),
+    ("storage", AnsiColor::Magenta),
+    ("keyword.operator", AnsiColor::Default),
+    (
+        "entity.name.function, support.function, variable.function",
+        AnsiColor::Blue,
+    ),
+    (
+        "entity.name.type, entity.name.class, support.type, support.class, storage.type",
+        AnsiColor::Yellow,
+    ),
+    ("entity.name.tag", AnsiColor::Red),
+    ("entity.other.attribute-name", AnsiColor::Yellow),
+    ("variable.parameter", AnsiColor::Red),
+    ("markup.heading", AnsiColor::Blue),
+    ("markup.inserted", AnsiColor::Green),
+    ("markup.deleted", AnsiColor::Red),
+    ("markup.changed", AnsiColor::Yellow),
+    ("invalid", AnsiColor::BrightRed),
+];
+
+/// Background colors for marked lines.
+pub const TINTS: Tints = Tints {
+    highlight: terminal::palette_color(AnsiColor::BrightBlack as u8),
+    inserted: terminal::palette_color(AnsiColor::Green as u8),
+    deleted: terminal::palette_color(AnsiColor::Red as u8),
+};
+
+/// Builds the theme for the `ansi_palette:` option: a list of
+/// `{selector, color}` pairs. A pair whose selector is identical to one in the
+/// default mapping replaces it, and the others are added after the defaults.
+///
+/// This produces an `:invalid_palette` failure if a selector cannot be parsed.
+pub fn decode<'env>(env: Env<'env>, opts: Term<'env>) -> NifResult<Result<Theme, Failure<'env>>> {
+    let custom: Vec<(String, AnsiColor)> =
+        opt(env, opts, atoms::ansi_palette())?.unwrap_or_default();
+
+    let mut entries = DEFAULT_PALETTE
+        .iter()
+        .filter(|(selector, _)| !custom.iter().any(|(sel, _)| sel == selector))
+        .copied()
+        .collect::<Vec<_>>();
+    entries.extend(
+        custom
+            .iter()
+            .map(|(selector, color)| (selector.as_str(), *color)),
+    );
+
+    let mut scopes = Vec::with_capacity(entries.len());
+    for (selector, color) in entries {
+        let scope = match ScopeSelectors::from_str(selector) {
+            Ok(scope) => scope,
+            Err(err) => {
+                let message = format!("invalid scope selector {:?}: {:?}", selector, err);
+                let failure = Failure::new(ErrorKind::InvalidPalette, message);
+                return Ok(Err(failure.value(selector.encode(env))));
+            }
+        };
+        scopes.push(ThemeItem {
+            scope,
+            style: StyleModifier {
+                foreground: Some(color.color()),
+                background: None,
+                font_style: None,
+            },
+        });
+    }
+
+    Ok(Ok(Theme {
+        name: Some("ANSI".into()),
+        settings: ThemeSettings {
+            foreground: Some(PALETTE_DEFAULT),
+            background: Some(PALETTE_DEFAULT),
+            gutter_foreground: Some(AnsiColor::BrightBlack.color()),
+            ..ThemeSettings::default()
+        },
+        scopes,
+        ..Theme::default()
+    }))
+}
@@ -0,0 +1,56 @@
+//! Shared storage for the library's languages and themes.
+//!
+//! Readers take an immutable snapshot of the current value, which they keep for
+//! as long as they need it. Writers copy the current value, modify the copy,
+//! and atomically swap it in. Readers therefore never wait on writers, and
+//! writers never wait on readers; writers only wait on each other, so that no
+//! modification is lost when two of them race.
+//!
+//! A writer that panics never swaps its copy in, so the current value is always
+//! whole, and the next writer carries on.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use arc_swap::ArcSwap;
+
+/// A value that is read through snapshots and replaced wholesale on write.
+pub struct Registry<T> {
+    current: ArcSwap<T>,
+    writer: Mutex<()>,
+}
+
+impl<T: Clone> Registry<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(value),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Gets the current value. Later modifications do not affect it.
+    pub fn snapshot(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+
+    /// Publishes a new value in place of the current one.
+    pub fn replace(&self, value: T) {
+        let _guard = self.lock();
+        self.current.store(Arc::new(value));
+    }
+
+    /// Modifies a copy of the current value, then publishes it for subsequent
+    /// snapshots to see.
+    pub fn modify<R>(&self, func: impl FnOnce(&mut T) -> R) -> R {
+        let _guard = self.lock();
+        let mut next = T::clone(&self.current.load());
+        let out = func(&mut next);
+        self.current.store(Arc::new(next));
+        out
+    }
+
+    /// Waits for other writers. The lock guards no data, so a writer that
+    /// panicked while holding it left nothing behind to distrust.
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
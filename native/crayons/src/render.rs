@@ -0,0 +1,340 @@
+//! Line-at-a-time rendering of highlighted text.
+//!
+//! A [`Painter`] holds everything that is fixed for the duration of a render:
+//! the language set, the theme, and the output format. The parser state that
+//! changes from line to line is kept separately in a [`LineState`], so that a
+//! render can be suspended between lines and resumed later.
+
+use std::fmt::Write;
+
+use rustler::{Atom, Encoder, Env, NifResult, Term};
+
+use syntect::{
+    highlighting::{Color, HighlightIterator, HighlightState, Highlighter, Style, Theme},
+    html::{start_highlighted_html_snippet, styled_line_to_highlighted_html, IncludeBackground},
+    parsing::{ParseState, ScopeStack, ScopeStackOp, SyntaxSet},
+};
+
+use crate::{
+    atoms,
+    errors::Failure,
+    gutter::Gutter,
+    html,
+    marks::{Marks, Tints},
+    opt, palette,
+    syntaxes::{self, Grammar},
+    terminal::{self, Depth, Layer},
+    ErrorKind,
+};
+
+/// The names of the formats that [`Format`] reads, to suggest when an unknown
+/// one is asked for.
+const FORMAT_NAMES: &[&str] = &[
+    "html",
+    "html_classed",
+    "terminal",
+    "terminal_256",
+    "terminal_16",
+    "terminal_8",
+    "terminal_mono",
+    "terminal_ansi",
+];
+
+/// The output formats that can be rendered line by line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// HTML with inline `style=` attributes.
+    Html,
+    /// HTML with scope-derived CSS classes, each carrying the prefix.
+    HtmlClassed { prefix: String },
+    /// Text with ANSI escapes, for a terminal that shows colors to some depth.
+    /// If `reset_lines` is set, every line ends with a reset, so that no line
+    /// depends on the state left by the one before it.
+    Terminal { depth: Depth, reset_lines: bool },
+}
+
+impl Format {
+    /// Reads a format atom, along with any options that it uses. Unknown
+    /// formats produce an `:unknown_format` failure, whose suggestions also
+    /// draw on `others`: the formats that the caller handles itself.
+    pub fn decode<'env>(
+        env: Env<'env>,
+        fmt: Atom,
+        opts: Term<'env>,
+        others: &[&str],
+    ) -> NifResult<Result<Self, Failure<'env>>> {
+        let depth = match fmt {
+            f if f == atoms::html() => return Ok(Ok(Self::Html)),
+            f if f == atoms::html_classed() => {
+                let prefix: String = opt(env, opts, atoms::class_prefix())?.unwrap_or_default();
+                if !html::valid_prefix(&prefix) {
+                    return Ok(Err(Failure::invalid_prefix(env, &prefix)));
+                }
+                return Ok(Ok(Self::HtmlClassed { prefix }));
+            }
+            f if f == atoms::terminal() => Depth::TrueColor,
+            f if f == atoms::terminal_256() => Depth::Xterm256,
+            f if f == atoms::terminal_16() => Depth::Ansi16,
+            f if f == atoms::terminal_8() => Depth::Ansi8,
+            f if f == atoms::terminal_mono() => Depth::Mono,
+            f if f == atoms::terminal_ansi() => Depth::Palette,
+            _ => {
+                let name = fmt.encode(env).atom_to_string()?;
+                let failure = Failure::new(
+                    ErrorKind::UnknownFormat,
+                    format!("unknown format: :{}", name),
+                )
+                .value(fmt.encode(env))
+                .suggest(&name, FORMAT_NAMES.iter().chain(others).copied());
+                return Ok(Err(failure));
+            }
+        };
+        Ok(Ok(Self::Terminal {
+            depth,
+            reset_lines: opt(env, opts, atoms::reset_lines())?.unwrap_or(false),
+        }))
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Terminal { .. })
+    }
+
+    /// Whether this format colors text with the terminal's own palette, and so
+    /// needs the theme from [`palette::decode`] in place of a named one.
+    pub fn uses_palette(&self) -> bool {
+        matches!(
+            self,
+            Self::Terminal {
+                depth: Depth::Palette,
+                ..
+            }
+        )
+    }
+}
+
+/// The parser and highlighter state carried from one line to the next.
+pub struct LineState {
+    parser: ParseState,
+    highlight: HighlightState,
+    stack: ScopeStack,
+    open: usize,
+    /// How many lines have been rendered.
+    count: usize,
+    /// Whether the grammar expects lines to end in `\n`.
+    newlines: bool,
+}
+
+impl LineState {
+    pub fn new(grammar: &Grammar, theme: &Theme) -> Self {
+        Self {
+            parser: ParseState::new(grammar.syntax),
+            highlight: HighlightState::new(&Highlighter::new(theme), ScopeStack::new()),
+            stack: ScopeStack::new(),
+            open: 0,
+            count: 0,
+            newlines: grammar.newlines,
+        }
+    }
+
+    /// Whether the grammar expects lines to end in `\n`, and so which of the
+    /// library's syntax sets it must be parsed with.
+    pub fn newlines(&self) -> bool {
+        self.newlines
+    }
+
+    /// Parses one line, which must not contain its line ending.
+    pub fn parse(&mut self, syntax_set: &SyntaxSet, line: &str) -> Vec<(usize, ScopeStackOp)> {
+        syntaxes::parse_line(&mut self.parser, syntax_set, line, self.newlines)
+    }
+
+    /// Parses and highlights one line, producing the styled regions in it.
+    pub fn styled<'l>(
+        &mut self,
+        syntax_set: &SyntaxSet,
+        highlighter: &Highlighter,
+        line: &'l str,
+    ) -> Vec<(Style, &'l str)> {
+        let ops = self.parse(syntax_set, line);
+        if !self.newlines {
+            return HighlightIterator::new(&mut self.highlight, &ops, line, highlighter).collect();
+        }
+
+        // The regions cover the `\n` that the parser was given, which is not
+        // part of the line.
+        let buf = format!("{}\n", line);
+        let mut start = 0;
+        let mut regions = vec![];
+        for (style, text) in HighlightIterator::new(&mut self.highlight, &ops, &buf, highlighter) {
+            let end = (start + text.len()).min(line.len());
+            if start < end {
+                regions.push((style, &line[start..end]));
+            }
+            start += text.len();
+        }
+        regions
+    }
+}
+
+/// Renders lines of text in one format, with one theme.
+pub struct Painter<'a> {
+    syntax_set: &'a SyntaxSet,
+    theme: &'a Theme,
+    highlighter: Highlighter<'a>,
+    format: &'a Format,
+    gutter: Option<&'a Gutter>,
+    marks: Option<(&'a Marks, Tints)>,
+    /// Passes terminal text through without highlighting it, only stripping
+    /// control sequences. This is used when the language is not known; HTML
+    /// formats need no special case, since plain text is already escaped.
+    plain: bool,
+}
+
+impl<'a> Painter<'a> {
+    pub fn new(syntax_set: &'a SyntaxSet, theme: &'a Theme, format: &'a Format) -> Self {
+        Self {
+            syntax_set,
+            theme,
+            highlighter: Highlighter::new(theme),
+            format,
+            gutter: None,
+            marks: None,
+            plain: false,
+        }
+    }
+
+    /// Makes the painter number each line in a gutter.
+    pub fn gutter(mut self, gutter: Option<&'a Gutter>) -> Self {
+        self.gutter = gutter;
+        self
+    }
+
+    /// Makes the painter emphasize, dim, or tint the marked lines.
+    pub fn marks(mut self, marks: Option<&'a Marks>) -> Self {
+        let tints = if self.format.uses_palette() {
+            palette::TINTS
+        } else {
+            Tints::new(self.theme)
+        };
+        self.marks = marks.map(|marks| (marks, tints));
+        self
+    }
+
+    /// Makes the painter treat its text as being in an unknown language.
+    pub fn plain(mut self) -> Self {
+        self.plain = true;
+        self
+    }
+
+    /// Appends whatever must precede the first line.
+    pub fn begin(&self, out: &mut String) {
+        match self.format {
+            Format::Html => out.push_str(&start_highlighted_html_snippet(self.theme).0),
+            Format::HtmlClassed { prefix } => html::start_classed_snippet(out, prefix),
+            Format::Terminal { .. } => {}
+        }
+    }
+
+    /// Appends one line of highlighted text. `line` must not contain its line
+    /// ending; the caller is responsible for appending it afterwards.
+    pub fn line(&self, state: &mut LineState, out: &mut String, line: &str) {
+        let index = state.count;
+        state.count += 1;
+        let (marks, tint) = match &self.marks {
+            Some((marks, tints)) => {
+                let marks = marks.line(index);
+                (marks, marks.tint(tints))
+            }
+            None => Default::default(),
+        };
+
+        match self.format {
+            Format::Html => {
+                if !marks.is_empty() {
+                    html::open_marked_line(out, &marks, tint);
+                }
+                if let Some(gutter) = self.gutter {
+                    gutter.html(out, gutter.number(index), self.theme);
+                }
+                let regions = state.styled(self.syntax_set, &self.highlighter, line);
+                out.push_str(&styled_line_to_highlighted_html(
+                    &regions,
+                    IncludeBackground::IfDifferent(self.background()),
+                ));
+                if !marks.is_empty() {
+                    out.push_str("</span>");
+                }
+            }
+            Format::HtmlClassed { prefix } => {
+                // Scope spans stay open from one line to the next, and line
+                // markup must not nest inside them, so they are closed around
+                // it and reopened afterwards.
+                let wrap = !marks.is_empty();
+                if wrap || self.gutter.is_some() {
+                    html::close_spans(out, state.open);
+                    if wrap {
+                        html::open_classed_marked_line(out, &marks, prefix);
+                    }
+                    if let Some(gutter) = self.gutter {
+                        gutter.html_classed(out, gutter.number(index), prefix);
+                    }
+                    html::reopen_spans(out, &state.stack, prefix, state.open);
+                }
+                let ops = state.parse(self.syntax_set, line);
+                html::classed_line(out, line, &ops, &mut state.stack, prefix, &mut state.open);
+                if wrap {
+                    html::close_spans(out, state.open);
+                    out.push_str("</span>");
+                    html::reopen_spans(out, &state.stack, prefix, state.open);
+                }
+            }
+            Format::Terminal { depth, reset_lines } => {
+                if let Some(gutter) = self.gutter {
+                    gutter.terminal(out, gutter.number(index), self.theme, *depth);
+                }
+                if marks.dimmed {
+                    out.push_str("\x1b[2m");
+                }
+                // Terminals without colors cannot show tints.
+                let tint = tint.filter(|_| *depth != Depth::Mono);
+                if let Some(sgr) = tint.and_then(|tint| depth.sgr(tint, Layer::Background)) {
+                    write!(out, "\x1b[{}m", sgr).unwrap();
+                }
+                if self.plain {
+                    out.push_str(&terminal::strip_controls(line));
+                } else {
+                    let mut regions = state.styled(self.syntax_set, &self.highlighter, line);
+                    if let Some(tint) = tint {
+                        for (style, _) in &mut regions {
+                            style.background = tint;
+                        }
+                    }
+                    terminal::escape(out, &regions, *depth, true, marks.dimmed);
+                }
+                if tint.is_some() {
+                    // Extends the tint to the right edge of the terminal.
+                    out.push_str("\x1b[K");
+                }
+                if (*reset_lines && !self.plain) || !marks.is_empty() {
+                    out.push_str("\x1b[0m");
+                }
+            }
+        }
+    }
+
+    /// Appends whatever must follow the last line.
+    pub fn finish(&self, state: &mut LineState, out: &mut String) {
+        match self.format {
+            Format::Html => out.push_str("</pre>\n"),
+            Format::HtmlClassed { .. } => {
+                html::finish_classed_snippet(out, state.open);
+                state.open = 0;
+            }
+            Format::Terminal { .. } if self.plain => {}
+            Format::Terminal { .. } => out.push_str("\x1b[0m"),
+        }
+    }
+
+    fn background(&self) -> Color {
+        self.theme.settings.background.unwrap_or(Color::WHITE)
+    }
+}
@@ -0,0 +1,107 @@
+//! Parsing without highlighting: the scope stack of every piece of text.
+
+use rustler::{Encoder, Env, Error as NifError, NifResult, Term};
+
+use syntect::{
+    parsing::{ParseState, ScopeStack},
+    util::LinesWithEndings,
+};
+
+use crate::{
+    detect::Lang,
+    reply,
+    syntaxes::{self, Grammar},
+    NifStatus, SYNTAXES,
+};
+
+/// A run of text with one scope stack.
+#[derive(rustler::NifMap, Clone, Debug)]
+pub struct ScopedToken {
+    /// The `{offset, length}` of the run, in bytes, as used by
+    /// `binary_part/3`.
+    pub range: (usize, usize),
+    /// The full scope stack, outermost first.
+    pub scopes: Vec<String>,
+}
+
+/// Parses text into runs with the same scope stack. Line endings are not
+/// included in any run.
+pub fn scope_tokens(grammar: &Grammar, text: &str) -> Vec<ScopedToken> {
+    let mut parser = ParseState::new(grammar.syntax);
+    let mut stack = ScopeStack::new();
+    let mut tokens: Vec<ScopedToken> = vec![];
+    let mut offset = 0;
+
+    for line in LinesWithEndings::from(text) {
+        let content = line.trim_end_matches(&['\r', '\n'][..]);
+        let ops = syntaxes::parse_line(&mut parser, grammar.set, content, grammar.newlines);
+
+        let mut cursor = 0;
+        let mut emit = |stack: &ScopeStack, from: usize, to: usize| {
+            if from >= to {
+                return;
+            }
+            let scopes = stack
+                .as_slice()
+                .iter()
+                .map(|scope| scope.build_string())
+                .collect::<Vec<_>>();
+            match tokens.last_mut() {
+                // Join runs that touch and share a stack, which happens when a
+                // scope is pushed and popped at the same position.
+                Some(last)
+                    if last.range.0 + last.range.1 == offset + from && last.scopes == scopes =>
+                {
+                    last.range.1 += to - from;
+                }
+                _ => tokens.push(ScopedToken {
+                    range: (offset + from, to - from),
+                    scopes,
+                }),
+            }
+        };
+        for (idx, op) in &ops {
+            let idx = (*idx).min(content.len());
+            emit(&stack, cursor, idx);
+            cursor = idx;
+            stack.apply(op);
+        }
+        emit(&stack, cursor, content.len());
+
+        offset += line.len();
+    }
+
+    tokens
+}
+
+/// Parses text with a language, producing its scopes without needing a theme.
+///
+/// # BEAM Arguments
+///
+/// - `text`: Some text to be parsed.
+/// - `lang`: An atom or string naming the language of the text.
+///
+/// # Returns
+///
+/// `{:ok, tokens}`, where each token is a map of its `range` within `text` and
+/// its `scopes`, or `{:fallback, tokens, lang}` if the language is not known
+/// and the text was parsed as `lang` instead.
+///
+/// Like [`color`](crate::color), this is exported as both `scopes` and
+/// `scopes_dirty`, for normal and dirty CPU schedulers respectively.
+pub fn scopes<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let text: &'env str = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+    let lang = Lang::decode(*args.get(1).ok_or(NifError::BadArg)?)?;
+
+    let syntaxes = SYNTAXES.snapshot();
+    let (syntax, status) = match lang.resolve(syntaxes.catalog(), text) {
+        Some(s) => (s, NifStatus::Ok),
+        None => (
+            syntaxes.catalog().find_syntax_plain_text(),
+            NifStatus::Fallback,
+        ),
+    };
+
+    let tokens = scope_tokens(&syntaxes.grammar(syntax), text);
+    Ok(reply(env, status, tokens, syntax))
+}
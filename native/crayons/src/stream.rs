@@ -0,0 +1,244 @@
+//! Highlighting of text that arrives in pieces.
+//!
+//! A stream is a BEAM resource that keeps the parser state between calls. Each
+//! chunk pushed into it is appended to a buffer, every completed line in the
+//! buffer is rendered and returned, and the trailing partial line is kept
+//! until more text arrives or the stream is finished.
+
+use std::sync::{Arc, Mutex};
+
+use rustler::{resource::ResourceArc, Atom, Encoder, Env, Error as NifError, NifResult, Term};
+
+use syntect::highlighting::Theme;
+
+use crate::{
+    atoms,
+    detect::{Lang, UnknownLang},
+    errors::Failure,
+    fail,
+    gutter::Gutter,
+    marks::Marks,
+    opt, palette,
+    render::{Format, LineState, Painter},
+    syntaxes::Syntaxes,
+    NifStatus, Themes, SYNTAXES, THEME_SET,
+};
+
+/// The resource handed to the BEAM.
+pub struct Stream {
+    inner: Mutex<State>,
+}
+
+struct State {
+    syntaxes: Arc<Syntaxes>,
+    theme_set: Arc<Themes>,
+    theme: String,
+    /// The theme made for the `:terminal_ansi` format, which replaces the named
+    /// one.
+    palette: Option<Theme>,
+    format: Format,
+    gutter: Option<Gutter>,
+    marks: Option<Marks>,
+    /// Whether the language was unknown, so the text is only escaped.
+    plain: bool,
+    lines: LineState,
+    /// Text after the last line ending seen so far.
+    pending: String,
+    started: bool,
+    finished: bool,
+}
+
+impl State {
+    /// Renders every complete line in the buffer, and the partial last line
+    /// too if `flush` is set.
+    fn drain(&mut self, flush: bool) -> String {
+        let Self {
+            syntaxes,
+            theme_set,
+            theme,
+            palette,
+            format,
+            gutter,
+            marks,
+            plain,
+            lines,
+            pending,
+            started,
+            ..
+        } = self;
+        let theme = palette
+            .as_ref()
+            .unwrap_or_else(|| &theme_set[theme.as_str()]);
+        let mut painter = Painter::new(syntaxes.set(lines.newlines()), theme, format)
+            .gutter(gutter.as_ref())
+            .marks(marks.as_ref());
+        if *plain {
+            painter = painter.plain();
+        }
+
+        let mut out = String::new();
+        if !*started {
+            painter.begin(&mut out);
+            *started = true;
+        }
+
+        let complete = match pending.rfind('\n') {
+            Some(idx) => idx + 1,
+            None => 0,
+        };
+        for line in pending[..complete].split_inclusive('\n') {
+            let content = line.trim_end_matches(&['\r', '\n'][..]);
+            painter.line(lines, &mut out, content);
+            out.push_str(&line[content.len()..]);
+        }
+        pending.drain(..complete);
+
+        if flush {
+            if !pending.is_empty() {
+                painter.line(lines, &mut out, pending);
+                pending.clear();
+            }
+            painter.finish(lines, &mut out);
+        }
+        out
+    }
+}
+
+/// Opens a new stream.
+///
+/// # BEAM Arguments
+///
+/// - `lang`: An atom or string naming the source language of the text.
+/// - `format`: One of the formats accepted by [`color`](crate::color).
+/// - `theme`: A theme name known to the library.
+/// - `opts`: The same options map accepted by [`color`](crate::color). Since
+///   the length of the text is not known in advance, line numbers are only as
+///   wide as `line_padding` requires, and may widen as the stream goes on.
+///   There is no text to detect a language from when the stream is opened, so
+///   `unknown_lang: :detect` colors an unknown language as plain text.
+///
+/// # Returns
+///
+/// `{:ok, stream}`, or `{:fallback, stream, lang}` if the language is not
+/// known. A fallback stream escapes HTML as plain text and strips control
+/// sequences from terminal text, just as [`color`](crate::color) does. Under
+/// `unknown_lang: :error`, an unknown language fails as it does for
+/// [`color`](crate::color).
+///
+/// The stream holds a snapshot of the library taken when it is opened, so
+/// languages and themes added afterwards do not affect it.
+pub fn stream_new<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let lang = Lang::decode(*args.get(0).ok_or(NifError::BadArg)?)?;
+    let fmt: Atom = args.get(1).ok_or(NifError::BadArg)?.decode()?;
+    let theme: &'env str = args.get(2).ok_or(NifError::BadArg)?.decode()?;
+    let opts = *args.get(3).ok_or(NifError::BadArg)?;
+
+    let syntaxes = SYNTAXES.snapshot();
+    let theme_set = THEME_SET.snapshot();
+
+    let theme_ref = match theme_set.get(theme) {
+        None => return fail(env, Failure::unknown_theme(env, theme, &theme_set)),
+        Some(t) => t,
+    };
+    let format = match Format::decode(env, fmt, opts, &[])? {
+        Err(failure) => return fail(env, failure),
+        Ok(f) => f,
+    };
+    let palette = if format.uses_palette() {
+        match palette::decode(env, opts)? {
+            Err(failure) => return fail(env, failure),
+            Ok(theme) => Some(theme),
+        }
+    } else {
+        None
+    };
+    let gutter = Gutter::decode(env, opts)?;
+    let marks = Marks::decode(env, opts)?;
+    let policy = opt(env, opts, atoms::unknown_lang())?.unwrap_or(UnknownLang::Plaintext);
+    let (syntax, plain) = match lang.resolve_or(env, syntaxes.catalog(), "", policy) {
+        Err(failure) => return fail(env, failure),
+        Ok(resolved) => (resolved.syntax, resolved.plain),
+    };
+
+    let lines = LineState::new(
+        &syntaxes.grammar(syntax),
+        palette.as_ref().unwrap_or(theme_ref),
+    );
+    let name = syntax.name.clone();
+    let stream = ResourceArc::new(Stream {
+        inner: Mutex::new(State {
+            syntaxes,
+            theme_set,
+            theme: theme.to_owned(),
+            palette,
+            format,
+            gutter,
+            marks,
+            plain,
+            lines,
+            pending: String::new(),
+            started: false,
+            finished: false,
+        }),
+    });
+
+    Ok(if plain {
+        (NifStatus::Fallback, stream, name).encode(env)
+    } else {
+        (NifStatus::Ok, stream).encode(env)
+    })
+}
+
+/// Pushes a chunk of text into a stream.
+///
+/// # BEAM Arguments
+///
+/// - `stream`: A stream opened by [`stream_new`].
+/// - `chunk`: The next piece of text. It may end anywhere, including in the
+///   middle of a line.
+///
+/// # Returns
+///
+/// `{:ok, colored}`, where `colored` contains every line completed by this
+/// chunk. The first output of a stream also contains the opening markup of its
+/// format.
+pub fn stream_push<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let stream: ResourceArc<Stream> = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+    let chunk: &'env str = args.get(1).ok_or(NifError::BadArg)?.decode()?;
+
+    let mut state = match stream.inner.lock() {
+        Ok(state) => state,
+        Err(_) => return fail(env, Failure::stream_poisoned()),
+    };
+    if state.finished {
+        return fail(env, Failure::stream_finished());
+    }
+    state.pending.push_str(chunk);
+    let out = state.drain(false);
+    Ok((NifStatus::Ok, out).encode(env))
+}
+
+/// Finishes a stream, rendering any partial line still buffered along with
+/// the closing markup of its format.
+///
+/// # BEAM Arguments
+///
+/// - `stream`: A stream opened by [`stream_new`].
+///
+/// # Returns
+///
+/// `{:ok, colored}`. Once finished, a stream refuses further text.
+pub fn stream_finish<'env>(env: Env<'env>, args: &[Term<'env>]) -> NifResult<Term<'env>> {
+    let stream: ResourceArc<Stream> = args.get(0).ok_or(NifError::BadArg)?.decode()?;
+
+    let mut state = match stream.inner.lock() {
+        Ok(state) => state,
+        Err(_) => return fail(env, Failure::stream_poisoned()),
+    };
+    if state.finished {
+        return fail(env, Failure::stream_finished());
+    }
+    state.finished = true;
+    let out = state.drain(true);
+    Ok((NifStatus::Ok, out).encode(env))
+}
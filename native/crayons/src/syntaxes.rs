@@ -0,0 +1,314 @@
+//! The library's languages, compiled for both ways of feeding text to them.
+//!
+//! `syntect` compiles a grammar either for lines that still end in `\n` or for
+//! lines that have had it removed, and a grammar written for one mode
+//! misbehaves in the other, since its `$` and `\n` patterns depend on it.
+//! Every language is compiled into a no-newlines set, which lookups by name,
+//! extension, or first line search, and each remembers which mode its author
+//! wrote it for. `syntect`'s own languages all expect no newlines, so a second
+//! set, compiled for newlines, is only built once a grammar that expects them
+//! is added; it holds every language, so that such grammars can embed others.
+//!
+//! Added grammars are also kept as they were loaded, so that the sets can be
+//! rebuilt without them when they are removed or replaced.
+
+use std::{
+    borrow::Cow,
+    fmt::{self, Display, Formatter},
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use syntect::parsing::{
+    ParseState, ParseSyntaxError, ScopeStackOp, SyntaxDefinition, SyntaxReference, SyntaxSet,
+};
+
+use crate::textmate::{self, ConvertError};
+
+lazy_static::lazy_static! {
+    /// `syntect`'s own languages, from which the sets are rebuilt. Once built,
+    /// languages refer to each other by their position in a set, so these can
+    /// be added to but never taken apart. Until a language is added, the
+    /// library's set is this one.
+    static ref BUILTIN: Arc<SyntaxSet> = Arc::new(SyntaxSet::load_defaults_nonewlines());
+    /// The same languages compiled for newlines, which are only loaded once an
+    /// added grammar expects newlines.
+    static ref BUILTIN_NEWLINES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
+
+/// Every language known to the library, compiled for the modes they need.
+///
+/// Cloning this copies pointers: the sets and the added grammars are shared
+/// with the clone until one of them is rebuilt.
+#[derive(Clone)]
+pub struct Syntaxes {
+    nonewlines: Arc<SyntaxSet>,
+    /// Every language compiled for newlines, if any added language expects
+    /// them.
+    newlines: Option<Arc<SyntaxSet>>,
+    /// The languages added since, oldest first.
+    added: Vec<Arc<Definition>>,
+}
+
+/// What [`Syntaxes::add`] does with a language whose name is already taken.
+#[derive(rustler::NifUnitEnum, Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Conflict {
+    /// Removes the added languages of that name first. A language built into
+    /// `syntect` cannot be removed, so the new one shadows it instead.
+    Replace,
+    /// Adds nothing, and reports the name.
+    Error,
+    /// Keeps the old languages beside the new one. Lookups find the newest.
+    KeepBoth,
+}
+
+/// A language as it is to be parsed: in the set that it was compiled into for
+/// its mode.
+#[derive(Clone, Copy)]
+pub struct Grammar<'s> {
+    pub set: &'s SyntaxSet,
+    pub syntax: &'s SyntaxReference,
+    /// Whether lines are fed to the parser with a `\n` at the end.
+    pub newlines: bool,
+}
+
+/// A grammar loaded in both modes, ready to be added to [`Syntaxes`].
+pub struct Definition {
+    nonewlines: SyntaxDefinition,
+    newlines: SyntaxDefinition,
+    /// The mode the grammar's author wrote it for.
+    wants_newlines: bool,
+}
+
+/// Why a grammar could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// A TextMate grammar could not be converted.
+    TextMate(ConvertError),
+    /// A `.sublime-syntax` grammar, or the conversion of a TextMate one, is
+    /// invalid.
+    Syntax(ParseSyntaxError),
+}
+
+impl Definition {
+    /// Loads a `.sublime-syntax` grammar, or a TextMate grammar in XML or JSON
+    /// form, which is converted to one first. `newlines` states which mode a
+    /// `.sublime-syntax` grammar was written for; TextMate grammars are always
+    /// written for lines that end in `\n`.
+    pub fn load(
+        source: &str,
+        newlines: bool,
+        fallback_name: Option<&str>,
+    ) -> Result<Self, LoadError> {
+        let (source, newlines) = match textmate::convert(source).map_err(LoadError::TextMate)? {
+            Some(converted) => (Cow::Owned(converted), true),
+            None => (Cow::Borrowed(source), newlines),
+        };
+        let load = |newlines| {
+            SyntaxDefinition::load_from_str(&source, newlines, fallback_name)
+                .map_err(LoadError::Syntax)
+        };
+        Ok(Self {
+            nonewlines: load(false)?,
+            newlines: load(true)?,
+            wants_newlines: newlines,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.nonewlines.name
+    }
+}
+
+impl Syntaxes {
+    /// Starts from `syntect`'s default languages, which all expect no
+    /// newlines.
+    pub fn load_defaults() -> Self {
+        Self {
+            nonewlines: Arc::clone(&BUILTIN),
+            newlines: None,
+            added: vec![],
+        }
+    }
+
+    /// The set to search for languages. Its references can be passed to
+    /// [`grammar`](Self::grammar).
+    pub fn catalog(&self) -> &SyntaxSet {
+        &self.nonewlines
+    }
+
+    /// The set that languages of one mode are parsed with.
+    pub fn set(&self, newlines: bool) -> &SyntaxSet {
+        match &self.newlines {
+            Some(set) if newlines => set,
+            _ => &self.nonewlines,
+        }
+    }
+
+    /// Finds the compilation of a language from the [`catalog`](Self::catalog)
+    /// that matches the mode it expects.
+    pub fn grammar<'s>(&'s self, syntax: &'s SyntaxReference) -> Grammar<'s> {
+        let added = self
+            .added
+            .iter()
+            .rev()
+            .find(|def| def.name() == syntax.name);
+        let wants_newlines = matches!(added, Some(def) if def.wants_newlines);
+        if let (true, Some(set)) = (wants_newlines, &self.newlines) {
+            if let Some(found) = set.find_syntax_by_name(&syntax.name) {
+                return Grammar {
+                    set,
+                    syntax: found,
+                    newlines: true,
+                };
+            }
+        }
+        Grammar {
+            set: &self.nonewlines,
+            syntax,
+            newlines: false,
+        }
+    }
+
+    /// The names of the added languages, which are the ones that can be
+    /// removed.
+    pub fn added(&self) -> impl Iterator<Item = &str> {
+        self.added.iter().map(|def| def.name())
+    }
+
+    /// Whether a language of this name, in any case, is loaded.
+    pub fn contains(&self, name: &str) -> bool {
+        self.nonewlines
+            .syntaxes()
+            .iter()
+            .any(|syntax| syntax.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Adds languages, rebuilding the sets once. The conflict policy applies as
+    /// though they were added one at a time, so later languages in the batch
+    /// also conflict with earlier ones. With [`Conflict::Error`], this adds
+    /// nothing if any of the names is taken, and produces that name.
+    pub fn add(
+        &mut self,
+        definitions: impl IntoIterator<Item = Definition>,
+        conflict: Conflict,
+    ) -> Result<(), String> {
+        let mut added = self.added.clone();
+        for definition in definitions {
+            let name = definition.name().to_owned();
+            let taken = |def: &Arc<Definition>| def.name().eq_ignore_ascii_case(&name);
+            match conflict {
+                Conflict::Error if self.contains(&name) || added.iter().any(taken) => {
+                    return Err(name);
+                }
+                Conflict::Replace => added.retain(|def| !taken(def)),
+                _ => {}
+            }
+            added.push(Arc::new(definition));
+        }
+        self.added = added;
+        self.rebuild();
+        Ok(())
+    }
+
+    /// Removes every added language of this name, in any case, producing the
+    /// name as it was loaded if there was one. `syntect`'s own languages cannot
+    /// be removed.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let found = self
+            .added
+            .iter()
+            .find(|def| def.name().eq_ignore_ascii_case(name))?
+            .name()
+            .to_owned();
+        self.added
+            .retain(|def| !def.name().eq_ignore_ascii_case(name));
+        self.rebuild();
+        Some(found)
+    }
+
+    /// Writes the compiled sets and the added grammars, so that they can be
+    /// restored without compiling anything.
+    pub fn dump(&self, out: impl Write) -> bincode::Result<()> {
+        let added = self
+            .added
+            .iter()
+            .map(|def| (&def.nonewlines, &def.newlines, def.wants_newlines))
+            .collect::<Vec<_>>();
+        let sets = (&*self.nonewlines, self.newlines.as_deref(), added);
+        bincode::serialize_into(out, &sets)
+    }
+
+    /// Reads what [`dump`](Self::dump) wrote. `syntect`'s own languages are
+    /// only needed to rebuild the sets, and are not loaded until then.
+    pub fn restore(input: impl Read) -> bincode::Result<Self> {
+        let (nonewlines, newlines, added): (SyntaxSet, Option<SyntaxSet>, Vec<(_, _, _)>) =
+            bincode::deserialize_from(input)?;
+        let added = added
+            .into_iter()
+            .map(|(nonewlines, newlines, wants_newlines)| {
+                Arc::new(Definition {
+                    nonewlines,
+                    newlines,
+                    wants_newlines,
+                })
+            })
+            .collect();
+        Ok(Self {
+            nonewlines: Arc::new(nonewlines),
+            newlines: newlines.map(Arc::new),
+            added,
+        })
+    }
+
+    /// Rebuilds the sets from `syntect`'s languages and the added ones. The
+    /// newlines set is only built if an added language expects newlines.
+    fn rebuild(&mut self) {
+        if self.added.is_empty() {
+            *self = Self::load_defaults();
+            return;
+        }
+        let mut nonewlines = SyntaxSet::clone(&BUILTIN).into_builder();
+        for def in &self.added {
+            nonewlines.add(def.nonewlines.clone());
+        }
+        self.nonewlines = Arc::new(nonewlines.build());
+
+        self.newlines = if self.added.iter().any(|def| def.wants_newlines) {
+            let mut newlines = BUILTIN_NEWLINES.clone().into_builder();
+            for def in &self.added {
+                newlines.add(def.newlines.clone());
+            }
+            Some(Arc::new(newlines.build()))
+        } else {
+            None
+        };
+    }
+}
+
+/// Parses one line, which must not contain its line ending, in the given mode.
+/// Operations may refer to the position just past the end of the line.
+pub fn parse_line(
+    parser: &mut ParseState,
+    set: &SyntaxSet,
+    line: &str,
+    newlines: bool,
+) -> Vec<(usize, ScopeStackOp)> {
+    if newlines {
+        let mut buf = String::with_capacity(line.len() + 1);
+        buf.push_str(line);
+        buf.push('\n');
+        parser.parse_line(&buf, set)
+    } else {
+        parser.parse_line(line, set)
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::TextMate(err) => Display::fmt(err, fmt),
+            Self::Syntax(err) => Display::fmt(err, fmt),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Helpers for text destined for a terminal.
+//!
+//! Not every terminal can show 24-bit color, so styled text can be escaped for
+//! several color depths. Theme colors are reduced to the smaller palettes by
+//! finding the perceptually nearest palette entry, measured in the Oklab color
+//! space, rather than the nearest by RGB value, which tends to wash colors out
+//! to grey.
+
+use std::{fmt::Write, iter::Peekable};
+
+use syntect::highlighting::{Color, FontStyle, Style};
+
+/// The colors a terminal can show.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Depth {
+    /// Any 24-bit color.
+    TrueColor,
+    /// The 6×6×6 color cube and 24-step grey ramp of xterm's 256 colors.
+    Xterm256,
+    /// The eight basic ANSI colors and their bright variants.
+    Ansi16,
+    /// Only the eight basic ANSI colors.
+    Ansi8,
+    /// No colors at all. Bold, italic, and underlined text is still marked.
+    Mono,
+    /// The terminal's own palette, addressed by colors made with
+    /// [`palette_color`] rather than by RGB value.
+    Palette,
+}
+
+/// The color that stands for the terminal's default foreground or background.
+/// Like the colors made by [`palette_color`], it is nearly transparent, which
+/// no theme color is in practice. Its alpha of 1, rather than their 0, keeps it
+/// apart from palette entry 0.
+pub const PALETTE_DEFAULT: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 1,
+};
+
+/// Makes a color that stands for entry `index` of the terminal's palette,
+/// rather than for an RGB value. Only [`Depth::Palette`] renders it as such.
+pub const fn palette_color(index: u8) -> Color {
+    Color {
+        r: index,
+        g: 0,
+        b: 0,
+        a: 0,
+    }
+}
+
+/// Whether a color applies to the text or behind it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Layer {
+    Foreground,
+    Background,
+}
+
+impl Depth {
+    /// Renders the SGR parameters that select a color, such as `38;5;208`, or
+    /// `None` if this depth has no colors.
+    pub fn sgr(self, color: Color, layer: Layer) -> Option<String> {
+        let Color { r, g, b, .. } = color;
+        let (base, bright) = match layer {
+            Layer::Foreground => (30, 90),
+            Layer::Background => (40, 100),
+        };
+        Some(match self {
+            Self::TrueColor => format!("{};2;{};{};{}", base + 8, r, g, b),
+            Self::Xterm256 => format!("{};5;{}", base + 8, nearest(&XTERM_256, color, 1.0)),
+            Self::Palette if color.a == 0 && color.r < 16 => match color.r as u32 {
+                idx @ 0..=7 => format!("{}", base + idx),
+                idx => format!("{}", bright + idx - 8),
+            },
+            Self::Palette if color.a == 1 => format!("{}", base + 9),
+            Self::Ansi16 | Self::Ansi8 | Self::Palette => {
+                let palette = if self == Self::Ansi8 {
+                    &ANSI_16[..8]
+                } else {
+                    &ANSI_16[..]
+                };
+                // The basic colors are so far apart that the nearest one is
+                // often grey; weighting chroma keeps colors from losing their
+                // hue.
+                match nearest(palette, color, 2.0) {
+                    idx @ 0..=7 => format!("{}", base + idx as u32),
+                    idx => format!("{}", bright + idx as u32 - 8),
+                }
+            }
+            Self::Mono => return None,
+        })
+    }
+}
+
+/// Appends styled text escaped for a terminal of the given depth.
+///
+/// Each region sets its own colors, and the background too if `background` is
+/// set. At [`Depth::TrueColor`], this matches syntect's
+/// `as_24_bit_terminal_escaped`. At [`Depth::Mono`], each region instead
+/// resets the terminal and sets its font style; `faint` keeps such resets from
+/// undoing a dimmed line.
+pub fn escape(
+    out: &mut String,
+    regions: &[(Style, &str)],
+    depth: Depth,
+    background: bool,
+    faint: bool,
+) {
+    for (style, text) in regions {
+        if depth == Depth::Mono {
+            out.push_str("\x1b[0");
+            if faint {
+                out.push_str(";2");
+            }
+            for (flag, code) in &[
+                (FontStyle::BOLD, ";1"),
+                (FontStyle::ITALIC, ";3"),
+                (FontStyle::UNDERLINE, ";4"),
+            ] {
+                if style.font_style.contains(*flag) {
+                    out.push_str(code);
+                }
+            }
+            out.push('m');
+        } else {
+            if background {
+                if let Some(sgr) = depth.sgr(style.background, Layer::Background) {
+                    write!(out, "\x1b[{}m", sgr).unwrap();
+                }
+            }
+            if let Some(sgr) = depth.sgr(style.foreground, Layer::Foreground) {
+                write!(out, "\x1b[{}m", sgr).unwrap();
+            }
+        }
+        out.push_str(text);
+    }
+}
+
+lazy_static::lazy_static! {
+    /// The palette entries of xterm's 256 colors that are not configurable:
+    /// the color cube and the grey ramp.
+    static ref XTERM_256: Vec<(u8, [f64; 3])> = {
+        const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+        let cube = (0..216u16).map(|idx| {
+            let (r, g, b) = (idx / 36, idx / 6 % 6, idx % 6);
+            let color = (LEVELS[r as usize], LEVELS[g as usize], LEVELS[b as usize]);
+            (16 + idx as u8, oklab(color))
+        });
+        let greys = (0..24u8).map(|idx| {
+            let level = 8 + 10 * idx;
+            (232 + idx, oklab((level, level, level)))
+        });
+        cube.chain(greys).collect()
+    };
+
+    /// xterm's default values for the sixteen ANSI colors.
+    static ref ANSI_16: Vec<(u8, [f64; 3])> = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(idx, &color)| (idx as u8, oklab(color)))
+    .collect();
+}
+
+/// Finds the palette index of the color nearest to `color`, with differences
+/// in chroma scaled by `chroma_weight` relative to differences in lightness.
+fn nearest(palette: &[(u8, [f64; 3])], color: Color, chroma_weight: f64) -> u8 {
+    let [l, a, b] = oklab((color.r, color.g, color.b));
+    let distance = |[l2, a2, b2]: [f64; 3]| {
+        (l - l2).powi(2) + chroma_weight.powi(2) * ((a - a2).powi(2) + (b - b2).powi(2))
+    };
+    palette
+        .iter()
+        .min_by(|(_, x), (_, y)| distance(*x).total_cmp(&distance(*y)))
+        .map(|(idx, _)| *idx)
+        .unwrap_or_default()
+}
+
+/// Converts an sRGB color to Oklab.
+fn oklab((r, g, b): (u8, u8, u8)) -> [f64; 3] {
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let l = (0.412_221_470_8 * r + 0.536_332_536_3 * g + 0.051_445_992_9 * b).cbrt();
+    let m = (0.211_903_498_2 * r + 0.680_699_545_1 * g + 0.107_396_956_6 * b).cbrt();
+    let s = (0.088_302_461_9 * r + 0.281_718_837_6 * g + 0.629_978_700_5 * b).cbrt();
+    [
+        0.210_454_255_3 * l + 0.793_617_785_0 * m - 0.004_072_046_8 * s,
+        1.977_998_495_1 * l - 2.428_592_205_0 * m + 0.450_593_709_9 * s,
+        0.025_904_037_1 * l + 0.782_771_766_2 * m - 0.808_675_766_0 * s,
+    ]
+}
+
+/// Removes escape sequences and other control characters from text, so that
+/// text which could not be colored is still safe to print.
+///
+/// Tabs, line feeds, and carriage returns are kept. Every other C0 and C1
+/// control character is dropped, along with the full body of any CSI, OSC,
+/// DCS, SOS, PM, or APC sequence it introduces. An `ESC` that does not start a
+/// sequence is dropped alone, and never takes a line ending with it.
+pub fn strip_controls(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\t' | '\n' | '\r' => out.push(ch),
+            '\x1b' => match chars.peek() {
+                Some('[') => {
+                    chars.next();
+                    skip_csi(&mut chars);
+                }
+                Some(']') | Some('P') | Some('X') | Some('^') | Some('_') => {
+                    chars.next();
+                    skip_string(&mut chars);
+                }
+                // Any intermediate bytes, then one final byte.
+                Some(&c) if ('\x20'..='\x7e').contains(&c) => {
+                    while chars.next_if(|c| ('\x20'..='\x2f').contains(c)).is_some() {}
+                    chars.next_if(|c| ('\x30'..='\x7e').contains(c));
+                }
+                _ => {}
+            },
+            '\u{9b}' => skip_csi(&mut chars),
+            '\u{9d}' | '\u{90}' | '\u{98}' | '\u{9e}' | '\u{9f}' => skip_string(&mut chars),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Skips the parameter, intermediate, and final bytes of a CSI sequence.
+fn skip_csi<I: Iterator<Item = char>>(chars: &mut Peekable<I>) {
+    while let Some(&c) = chars.peek() {
+        if ('\x20'..='\x3f').contains(&c) {
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    if let Some(&c) = chars.peek() {
+        if ('\x40'..='\x7e').contains(&c) {
+            chars.next();
+        }
+    }
+}
+
+/// Skips the body of a string sequence, up to and including its terminator:
+/// either `BEL`, `ESC \`, or the C1 `ST`.
+fn skip_string<I: Iterator<Item = char>>(chars: &mut Peekable<I>) {
+    while let Some(c) = chars.next() {
+        match c {
+            '\x07' | '\u{9c}' => return,
+            '\x1b' => {
+                if chars.peek() == Some(&'\\') {
+                    chars.next();
+                }
+                return;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,425 @@
+//! Conversion of TextMate grammars into `.sublime-syntax` grammars.
+//!
+//! TextMate grammars, which VS Code also uses, are property lists written
+//! either as XML (`.tmLanguage`) or as JSON (`.tmLanguage.json`). `syntect`
+//! only loads `.sublime-syntax` YAML, so they are rewritten into that form:
+//! the top-level patterns become the `main` context, each repository entry
+//! becomes a named context, and each `begin`/`end` rule pushes an anonymous
+//! context that its `end` pattern pops. Constructs with no counterpart, such
+//! as `while` rules and injections, are reported by where they occur rather
+//! than silently dropped.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    io::Cursor,
+};
+
+use plist::{Dictionary, Value};
+
+use yaml_rust::{yaml::Hash, Yaml, YamlEmitter};
+
+/// Why a TextMate grammar could not be converted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConvertError {
+    /// The grammar is not a well-formed property list or JSON document.
+    Parse(String),
+    /// The grammar lacks a key that every grammar needs.
+    Missing(&'static str),
+    /// The grammar uses constructs that cannot be converted.
+    Unsupported(Vec<Unsupported>),
+}
+
+/// A construct with no `.sublime-syntax` counterpart, and where it occurs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unsupported {
+    /// The path to the construct from the root of the grammar, such as
+    /// `repository.heredoc.patterns[2]`.
+    pub path: String,
+    pub construct: &'static str,
+}
+
+/// Converts a TextMate grammar into `.sublime-syntax` YAML, producing
+/// `Ok(None)` if `source` is not a TextMate grammar at all.
+pub fn convert(source: &str) -> Result<Option<String>, ConvertError> {
+    let source = source.trim_start_matches('\u{feff}').trim_start();
+    let root = if source.starts_with('<') {
+        Value::from_reader_xml(Cursor::new(source.as_bytes()))
+            .map_err(|err| ConvertError::Parse(err.to_string()))?
+    } else if source.starts_with('{') {
+        serde_json::from_str(source)
+            .map(from_json)
+            .map_err(|err| ConvertError::Parse(err.to_string()))?
+    } else {
+        return Ok(None);
+    };
+    let grammar = root
+        .as_dictionary()
+        .ok_or_else(|| ConvertError::Parse("the grammar is not a dictionary".into()))?;
+
+    let mut converter = Converter::default();
+    let syntax = converter.grammar(grammar)?;
+    if !converter.unsupported.is_empty() {
+        return Err(ConvertError::Unsupported(converter.unsupported));
+    }
+
+    let mut out = String::new();
+    YamlEmitter::new(&mut out)
+        .dump(&syntax)
+        .map_err(|err| ConvertError::Parse(format!("{:?}", err)))?;
+    Ok(Some(out))
+}
+
+#[derive(Default)]
+struct Converter {
+    /// The named contexts made so far, other than `main`.
+    contexts: Hash,
+    /// The repositories in scope, innermost last, mapping each of their keys
+    /// to the name of its context.
+    repositories: Vec<HashMap<String, String>>,
+    unsupported: Vec<Unsupported>,
+}
+
+impl Converter {
+    fn grammar(&mut self, grammar: &Dictionary) -> Result<Yaml, ConvertError> {
+        let scope = string(grammar, "scopeName").ok_or(ConvertError::Missing("scopeName"))?;
+        for key in &["injections", "injectionSelector"] {
+            if grammar.contains_key(key) {
+                self.unsupported(key.to_string(), "injections");
+            }
+        }
+
+        let repository = self.enter(grammar, "", None);
+        let mut main = vec![];
+        self.patterns(grammar, "", &mut main);
+        if repository {
+            self.repositories.pop();
+        }
+
+        let mut syntax = Hash::new();
+        if let Some(name) = string(grammar, "name") {
+            syntax.insert(key("name"), text(name));
+        }
+        syntax.insert(key("scope"), text(scope));
+        if let Some(types) = grammar.get("fileTypes").and_then(Value::as_array) {
+            let types = types
+                .iter()
+                .filter_map(Value::as_string)
+                .map(text)
+                .collect();
+            syntax.insert(key("file_extensions"), Yaml::Array(types));
+        }
+        if let Some(first_line) = string(grammar, "firstLineMatch") {
+            syntax.insert(key("first_line_match"), text(first_line));
+        }
+        if flag(grammar, "hideFromUser") {
+            syntax.insert(key("hidden"), Yaml::Boolean(true));
+        }
+
+        let mut contexts = Hash::new();
+        contexts.insert(key("main"), Yaml::Array(main));
+        contexts.extend(std::mem::take(&mut self.contexts));
+        syntax.insert(key("contexts"), Yaml::Hash(contexts));
+        Ok(Yaml::Hash(syntax))
+    }
+
+    /// Brings the `repository` of a grammar or rule into scope, converting
+    /// each of its entries into a named context. Returns whether there was a
+    /// repository, which the caller must pop once it leaves the rule.
+    fn enter(&mut self, rule: &Dictionary, path: &str, parent: Option<&str>) -> bool {
+        let repository = match rule.get("repository").and_then(Value::as_dictionary) {
+            Some(repository) => repository,
+            None => return false,
+        };
+        // Every name is registered before any entry is converted, since
+        // entries may include each other in any order.
+        let names = repository
+            .keys()
+            .map(|entry| {
+                let name = match parent {
+                    Some(parent) => format!("{}/{}", parent, entry),
+                    None if entry == "main" || entry == "prototype" => {
+                        format!("{}-repository", entry)
+                    }
+                    None => entry.clone(),
+                };
+                (entry.clone(), name)
+            })
+            .collect::<HashMap<_, _>>();
+        self.repositories.push(names.clone());
+
+        for (entry, value) in repository {
+            let path = join(path, &format!("repository.{}", entry));
+            let mut context = vec![];
+            if let Some(rule) = value.as_dictionary() {
+                self.rule(rule, &path, Some(&names[entry.as_str()]), &mut context);
+            }
+            self.contexts
+                .insert(text(&names[entry.as_str()]), Yaml::Array(context));
+        }
+        true
+    }
+
+    /// Converts the `patterns` of a grammar or rule.
+    fn patterns(&mut self, rule: &Dictionary, path: &str, out: &mut Vec<Yaml>) {
+        let patterns = match rule.get("patterns").and_then(Value::as_array) {
+            Some(patterns) => patterns,
+            None => return,
+        };
+        for (idx, pattern) in patterns.iter().enumerate() {
+            if let Some(pattern) = pattern.as_dictionary() {
+                let path = join(path, &format!("patterns[{}]", idx));
+                self.rule(pattern, &path, None, out);
+            }
+        }
+    }
+
+    /// Converts one rule into the patterns of a context. `name` is the
+    /// context's name when the rule is a repository entry, which scopes any
+    /// repository nested inside it.
+    fn rule(&mut self, rule: &Dictionary, path: &str, name: Option<&str>, out: &mut Vec<Yaml>) {
+        if flag(rule, "disabled") {
+            return;
+        }
+        let repository = self.enter(rule, path, name.or(Some(path)));
+
+        if let Some(include) = string(rule, "include") {
+            if let Some(reference) = self.reference(include) {
+                let mut pattern = Hash::new();
+                pattern.insert(key("include"), text(&reference));
+                out.push(Yaml::Hash(pattern));
+            }
+        } else if let Some(regex) = string(rule, "match") {
+            let mut pattern = Hash::new();
+            pattern.insert(key("match"), text(regex));
+            self.captures(
+                rule,
+                "captures",
+                path,
+                &[string(rule, "name")],
+                &mut pattern,
+            );
+            out.push(Yaml::Hash(pattern));
+        } else if let Some(begin) = string(rule, "begin") {
+            self.begin_end(rule, begin, path, out);
+        } else {
+            self.patterns(rule, path, out);
+        }
+
+        if repository {
+            self.repositories.pop();
+        }
+    }
+
+    /// Converts a `begin`/`end` rule into a pattern that pushes a context,
+    /// which the `end` pattern pops.
+    fn begin_end(&mut self, rule: &Dictionary, begin: &str, path: &str, out: &mut Vec<Yaml>) {
+        if rule.contains_key("while") {
+            return self.unsupported(path.into(), "`while` rules");
+        }
+        let end = match string(rule, "end") {
+            Some(end) => end,
+            None => return self.unsupported(path.into(), "`begin` rules without `end`"),
+        };
+        let name = string(rule, "name");
+        self.check_scope(name, path);
+        let content_name = string(rule, "contentName");
+        self.check_scope(content_name, path);
+
+        let mut context = vec![];
+        for (meta, scope) in &[("meta_scope", name), ("meta_content_scope", content_name)] {
+            if let Some(scope) = scope {
+                let mut pattern = Hash::new();
+                pattern.insert(key(meta), text(scope));
+                context.push(Yaml::Hash(pattern));
+            }
+        }
+
+        let mut pop = Hash::new();
+        pop.insert(key("match"), text(end));
+        let captures = if rule.contains_key("endCaptures") {
+            "endCaptures"
+        } else {
+            "captures"
+        };
+        self.captures(rule, captures, path, &[], &mut pop);
+        pop.insert(key("pop"), Yaml::Boolean(true));
+
+        let end_last = flag(rule, "applyEndPatternLast");
+        if !end_last {
+            context.push(Yaml::Hash(pop.clone()));
+        }
+        self.patterns(rule, path, &mut context);
+        if end_last {
+            context.push(Yaml::Hash(pop));
+        }
+
+        let mut push = Hash::new();
+        push.insert(key("match"), text(begin));
+        let captures = if rule.contains_key("beginCaptures") {
+            "beginCaptures"
+        } else {
+            "captures"
+        };
+        self.captures(rule, captures, path, &[], &mut push);
+        push.insert(key("push"), Yaml::Array(context));
+        out.push(Yaml::Hash(push));
+    }
+
+    /// Copies a rule's captures onto a pattern. The whole match, capture 0,
+    /// joins `scopes` in the pattern's `scope`, since `.sublime-syntax`
+    /// grammars scope the whole match there.
+    fn captures(
+        &mut self,
+        rule: &Dictionary,
+        field: &str,
+        path: &str,
+        scopes: &[Option<&str>],
+        pattern: &mut Hash,
+    ) {
+        let mut whole = scopes.iter().flatten().copied().collect::<Vec<_>>();
+        let mut groups = Hash::new();
+        if let Some(captures) = rule.get(field).and_then(Value::as_dictionary) {
+            for (group, capture) in captures {
+                let capture = match capture.as_dictionary() {
+                    Some(capture) => capture,
+                    None => continue,
+                };
+                let path = join(path, &format!("{}.{}", field, group));
+                if capture.contains_key("patterns") {
+                    self.unsupported(path.clone(), "patterns inside captures");
+                }
+                let (group, scope) = match (group.parse::<i64>(), string(capture, "name")) {
+                    (Ok(group), Some(scope)) => (group, scope),
+                    _ => continue,
+                };
+                if group == 0 {
+                    whole.push(scope);
+                } else {
+                    groups.insert(Yaml::Integer(group), text(scope));
+                }
+            }
+        }
+        for scope in &whole {
+            self.check_scope(Some(scope), path);
+        }
+        for scope in groups.values() {
+            self.check_scope(scope.as_str(), path);
+        }
+
+        if !whole.is_empty() {
+            pattern.insert(key("scope"), text(&whole.join(" ")));
+        }
+        if !groups.is_empty() {
+            pattern.insert(key("captures"), Yaml::Hash(groups));
+        }
+    }
+
+    /// Translates the target of an `include`. References to repository
+    /// entries that do not exist are dropped, as TextMate ignores them.
+    fn reference(&self, include: &str) -> Option<String> {
+        match include {
+            "$self" | "$base" => Some("main".into()),
+            local if local.starts_with('#') => self
+                .repositories
+                .iter()
+                .rev()
+                .find_map(|names| names.get(&local[1..]))
+                .cloned(),
+            scope => Some(format!("scope:{}", scope)),
+        }
+    }
+
+    /// Reports scope names that use text captured by the match, such as
+    /// `entity.name.$1`, which `syntect` cannot fill in.
+    fn check_scope(&mut self, scope: Option<&str>, path: &str) {
+        let scope = match scope {
+            Some(scope) => scope,
+            None => return,
+        };
+        let captured = scope
+            .split('$')
+            .skip(1)
+            .any(|rest| rest.starts_with(|c: char| c.is_ascii_digit() || c == '{'));
+        if captured {
+            self.unsupported(path.into(), "captured text in scope names");
+        }
+    }
+
+    fn unsupported(&mut self, path: String, construct: &'static str) {
+        let found = Unsupported { path, construct };
+        if !self.unsupported.contains(&found) {
+            self.unsupported.push(found);
+        }
+    }
+}
+
+impl Display for ConvertError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(fmt, "invalid TextMate grammar: {}", err),
+            Self::Missing(key) => write!(fmt, "TextMate grammar has no `{}`", key),
+            Self::Unsupported(found) => {
+                fmt.write_str("unsupported TextMate grammar constructs:")?;
+                for Unsupported { path, construct } in found {
+                    write!(fmt, "\n- {} at {}", construct, path)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads a JSON grammar into the same shape as a property list. Keys whose
+/// values are `null` are dropped, as property lists cannot express them.
+fn from_json(value: serde_json::Value) -> Value {
+    use serde_json::Value as Json;
+    match value {
+        Json::Null => Value::String(String::new()),
+        Json::Bool(b) => Value::Boolean(b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i.into()),
+            None => Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        Json::String(s) => Value::String(s),
+        Json::Array(items) => Value::Array(items.into_iter().map(from_json).collect()),
+        Json::Object(entries) => Value::Dictionary(
+            entries
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, from_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn string<'a>(dict: &'a Dictionary, field: &str) -> Option<&'a str> {
+    dict.get(field).and_then(Value::as_string)
+}
+
+/// Reads a boolean the ways TextMate grammars write them: as a boolean, or
+/// as `1` or `0`.
+fn flag(dict: &Dictionary, field: &str) -> bool {
+    match dict.get(field) {
+        Some(Value::Boolean(b)) => *b,
+        Some(Value::Integer(i)) => i.as_signed() != Some(0),
+        Some(Value::String(s)) => s == "1" || s == "true",
+        _ => false,
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.into()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+fn key(name: &str) -> Yaml {
+    Yaml::String(name.into())
+}
+
+fn text(value: &str) -> Yaml {
+    Yaml::String(value.into())
+}
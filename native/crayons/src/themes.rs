@@ -0,0 +1,551 @@
+//! Themes in the formats that editors ship them in.
+//!
+//! Besides `.tmTheme` property lists, which `syntect` reads itself, this reads
+//! VS Code color themes and Sublime Text's `.sublime-color-scheme` files. A VS
+//! Code theme's `tokenColors` are TextMate theme rules, and its `colors` name
+//! parts of the editor, which map onto the theme's settings. A color scheme's
+//! colors are CSS colors that may refer to its shared `variables`. Both are
+//! JSON that may contain comments and trailing commas.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io::Cursor,
+    str::FromStr,
+};
+
+use serde_json::{Map, Value as Json};
+
+use syntect::{
+    highlighting::{
+        Color, FontStyle, ScopeSelectors, StyleModifier, Theme, ThemeItem, ThemeSet, ThemeSettings,
+    },
+    LoadingError,
+};
+
+/// Why a theme could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// A `.tmTheme` property list is invalid.
+    TmTheme(LoadingError),
+    /// A JSON theme is not well-formed.
+    Json(String),
+    /// Part of a JSON theme cannot be read.
+    Invalid { path: String, reason: String },
+}
+
+/// How deeply color scheme variables may refer to each other, which stops
+/// cycles.
+const MAX_DEPTH: usize = 16;
+
+/// The VS Code editor colors that have counterparts among a theme's settings,
+/// and the names of those settings.
+const EDITOR_COLORS: &[(&str, &str)] = &[
+    ("editor.foreground", "foreground"),
+    ("editor.background", "background"),
+    ("editorCursor.foreground", "caret"),
+    ("editor.lineHighlightBackground", "line_highlight"),
+    ("editor.selectionBackground", "selection"),
+    ("editor.selectionForeground", "selection_foreground"),
+    ("editor.inactiveSelectionBackground", "inactive_selection"),
+    ("editor.findMatchHighlightBackground", "find_highlight"),
+    ("editor.wordHighlightBackground", "highlight"),
+    ("editorGutter.background", "gutter"),
+    ("editorLineNumber.foreground", "gutter_foreground"),
+    ("editorIndentGuide.background", "guide"),
+    ("editorIndentGuide.activeBackground", "active_guide"),
+    ("editorBracketMatch.border", "brackets_foreground"),
+];
+
+/// The CSS named colors that color schemes most often use.
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("black", 0x000000),
+    ("silver", 0xc0c0c0),
+    ("gray", 0x808080),
+    ("grey", 0x808080),
+    ("white", 0xffffff),
+    ("maroon", 0x800000),
+    ("red", 0xff0000),
+    ("purple", 0x800080),
+    ("fuchsia", 0xff00ff),
+    ("green", 0x008000),
+    ("lime", 0x00ff00),
+    ("olive", 0x808000),
+    ("yellow", 0xffff00),
+    ("navy", 0x000080),
+    ("blue", 0x0000ff),
+    ("teal", 0x008080),
+    ("aqua", 0x00ffff),
+];
+
+/// Loads a theme from a `.tmTheme` property list, a VS Code color theme, or a
+/// `.sublime-color-scheme` file.
+pub fn load(source: &[u8]) -> Result<Theme, LoadError> {
+    let json = std::str::from_utf8(source)
+        .ok()
+        .map(|text| text.trim_start_matches('\u{feff}').trim_start())
+        .filter(|text| text.starts_with('{'));
+    let json = match json {
+        Some(json) => json,
+        None => {
+            return ThemeSet::load_from_reader(&mut Cursor::new(source)).map_err(LoadError::TmTheme)
+        }
+    };
+
+    let root = serde_json::from_str::<Json>(&strip_comments(json))
+        .map_err(|err| LoadError::Json(err.to_string()))?;
+    let root = match root.as_object() {
+        Some(root) => root,
+        None => return Err(LoadError::Json("the theme is not an object".into())),
+    };
+    if ["rules", "globals", "variables"]
+        .iter()
+        .any(|key| root.contains_key(*key))
+    {
+        color_scheme(root)
+    } else {
+        vscode(root)
+    }
+}
+
+/// Reads a VS Code color theme.
+fn vscode(root: &Map<String, Json>) -> Result<Theme, LoadError> {
+    if root.contains_key("include") {
+        return Err(invalid("include", "themes that include other files"));
+    }
+    let mut theme = Theme {
+        name: root.get("name").and_then(Json::as_str).map(Into::into),
+        author: root.get("author").and_then(Json::as_str).map(Into::into),
+        ..Theme::default()
+    };
+
+    match root.get("tokenColors") {
+        None => {}
+        Some(Json::Array(rules)) => {
+            for (idx, rule) in rules.iter().enumerate() {
+                let path = format!("tokenColors[{}]", idx);
+                let settings = rule.get("settings").and_then(Json::as_object);
+                let settings = match settings {
+                    Some(settings) => settings,
+                    None => continue,
+                };
+                let style = StyleModifier {
+                    foreground: hex_field(settings, "foreground", &path)?,
+                    background: hex_field(settings, "background", &path)?,
+                    font_style: settings
+                        .get("fontStyle")
+                        .and_then(Json::as_str)
+                        .map(font_style),
+                };
+                // A rule without a scope sets the defaults, as in a `.tmTheme`.
+                match rule.get("scope") {
+                    None => {
+                        theme.settings.foreground = style.foreground;
+                        theme.settings.background = style.background;
+                    }
+                    Some(scope) => theme.scopes.push(ThemeItem {
+                        scope: selectors(scope, &format!("{}.scope", path))?,
+                        style,
+                    }),
+                }
+            }
+        }
+        Some(_) => return Err(invalid("tokenColors", "themes that include other files")),
+    }
+
+    if let Some(colors) = root.get("colors").and_then(Json::as_object) {
+        for (editor, setting) in EDITOR_COLORS {
+            if let Some(color) = hex_field(colors, editor, "colors")? {
+                *field(&mut theme.settings, setting).expect("known setting") = Some(color);
+            }
+        }
+    }
+    Ok(theme)
+}
+
+/// Reads a Sublime Text `.sublime-color-scheme` file.
+fn color_scheme(root: &Map<String, Json>) -> Result<Theme, LoadError> {
+    let empty = Map::new();
+    let variables = root
+        .get("variables")
+        .and_then(Json::as_object)
+        .unwrap_or(&empty);
+    let color = |value: &Json, path: &str| -> Result<Color, LoadError> {
+        let value = value
+            .as_str()
+            .ok_or_else(|| invalid(path, "colors that are not strings"))?;
+        css_color(value, variables, 0).map_err(|reason| LoadError::Invalid {
+            path: path.into(),
+            reason,
+        })
+    };
+
+    let mut theme = Theme {
+        name: root.get("name").and_then(Json::as_str).map(Into::into),
+        author: root.get("author").and_then(Json::as_str).map(Into::into),
+        ..Theme::default()
+    };
+
+    if let Some(globals) = root.get("globals").and_then(Json::as_object) {
+        for (key, value) in globals {
+            if let Some(setting) = field(&mut theme.settings, key) {
+                *setting = Some(color(value, &format!("globals.{}", key))?);
+            }
+        }
+    }
+
+    if let Some(rules) = root.get("rules").and_then(Json::as_array) {
+        for (idx, rule) in rules.iter().enumerate() {
+            let path = format!("rules[{}]", idx);
+            let scope = match rule.get("scope") {
+                Some(scope) => selectors(scope, &format!("{}.scope", path))?,
+                None => continue,
+            };
+            let mut style = StyleModifier::default();
+            for (key, slot) in &mut [
+                ("foreground", &mut style.foreground),
+                ("background", &mut style.background),
+            ] {
+                let path = format!("{}.{}", path, key);
+                match rule.get(*key) {
+                    None => {}
+                    Some(Json::Array(_)) => return Err(invalid(&path, "gradient colors")),
+                    Some(value) => **slot = Some(color(value, &path)?),
+                }
+            }
+            style.font_style = rule
+                .get("font_style")
+                .and_then(Json::as_str)
+                .map(font_style);
+            theme.scopes.push(ThemeItem { scope, style });
+        }
+    }
+    Ok(theme)
+}
+
+/// Reads a CSS color as a color scheme writes it: as a hex, `rgb()`, `hsl()`,
+/// or named color, as `var(name)`, or as `color(base alpha(a))`.
+fn css_color(value: &str, variables: &Map<String, Json>, depth: usize) -> Result<Color, String> {
+    let value = value.trim();
+    if depth > MAX_DEPTH {
+        return Err(format!("variable `{}` refers to itself", value));
+    }
+    if let Some(name) = call(value, "var") {
+        return match variables.get(name.trim()).and_then(Json::as_str) {
+            Some(value) => css_color(value, variables, depth + 1),
+            None => Err(format!("unknown variable `{}`", name.trim())),
+        };
+    }
+    if let Some(body) = call(value, "color") {
+        let body = body.trim();
+        let split = base_end(body);
+        let mut color = css_color(&body[..split], variables, depth + 1)?;
+        let mut rest = body[split..].trim();
+        while !rest.is_empty() {
+            let end = rest.find(')').map_or(rest.len(), |idx| idx + 1);
+            let adjuster = &rest[..end];
+            match call(adjuster, "alpha").or_else(|| call(adjuster, "a")) {
+                Some(alpha) => color.a = unit(alpha.trim(), 1.0)?,
+                None => return Err(format!("unsupported color adjuster `{}`", adjuster)),
+            }
+            rest = rest[end..].trim();
+        }
+        return Ok(color);
+    }
+    for name in &["rgb", "rgba"] {
+        if let Some(args) = call(value, name) {
+            let args = arguments(args);
+            if args.len() < 3 {
+                return Err(format!("invalid color `{}`", value));
+            }
+            let channel = |arg: &str| unit(arg, 255.0);
+            return Ok(Color {
+                r: channel(args[0])?,
+                g: channel(args[1])?,
+                b: channel(args[2])?,
+                a: args.get(3).map_or(Ok(0xff), |arg| unit(arg, 1.0))?,
+            });
+        }
+    }
+    for name in &["hsl", "hsla"] {
+        if let Some(args) = call(value, name) {
+            let args = arguments(args);
+            if args.len() < 3 {
+                return Err(format!("invalid color `{}`", value));
+            }
+            let number = |arg: &str| {
+                arg.trim_end_matches(&['%', 'd', 'e', 'g'][..])
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid color `{}`", value))
+            };
+            let (r, g, b) = hsl(
+                number(args[0])?,
+                number(args[1])? / 100.0,
+                number(args[2])? / 100.0,
+            );
+            return Ok(Color {
+                r,
+                g,
+                b,
+                a: args.get(3).map_or(Ok(0xff), |arg| unit(arg, 1.0))?,
+            });
+        }
+    }
+    if let Some(hex) = hex(value) {
+        return Ok(hex);
+    }
+    if value.eq_ignore_ascii_case("transparent") {
+        return Ok(Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        });
+    }
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| value.eq_ignore_ascii_case(name))
+        .map(|&(_, rgb)| Color {
+            r: (rgb >> 16) as u8,
+            g: (rgb >> 8) as u8,
+            b: rgb as u8,
+            a: 0xff,
+        })
+        .ok_or_else(|| format!("unsupported color `{}`", value))
+}
+
+/// Finds the end of the base color in the body of a `color()` adjustment,
+/// which may itself contain parentheses.
+fn base_end(body: &str) -> usize {
+    let mut depth = 0;
+    for (idx, c) in body.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => return idx,
+            _ => {}
+        }
+    }
+    body.len()
+}
+
+/// Gets the arguments of `name(...)` if `value` is such a call.
+fn call<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    let rest = value.strip_prefix(name)?.trim_start();
+    rest.strip_prefix('(')?.strip_suffix(')')
+}
+
+/// Splits CSS function arguments, which may be separated by commas, spaces,
+/// or a slash before the alpha.
+fn arguments(args: &str) -> Vec<&str> {
+    args.split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .collect()
+}
+
+/// Reads a number out of `max`, or a percentage, as a byte.
+fn unit(arg: &str, max: f32) -> Result<u8, String> {
+    let value = match arg.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().map(|p| p / 100.0),
+        None => arg.parse::<f32>().map(|n| n / max),
+    }
+    .map_err(|_| format!("invalid color component `{}`", arg))?;
+    Ok((value.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn hsl(hue: f32, saturation: f32, lightness: f32) -> (u8, u8, u8) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    let byte = |v: f32| ((v + m).clamp(0.0, 1.0) * 255.0).round() as u8;
+    (byte(r), byte(g), byte(b))
+}
+
+/// Reads a `#rgb`, `#rgba`, `#rrggbb`, or `#rrggbbaa` color.
+fn hex(value: &str) -> Option<Color> {
+    let digits = value.strip_prefix('#')?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |idx: usize, width: usize| {
+        let digits = &digits[idx * width..(idx + 1) * width];
+        u8::from_str_radix(digits, 16)
+            .ok()
+            .map(|v| if width == 1 { v * 0x11 } else { v })
+    };
+    let (width, alpha) = match digits.len() {
+        3 => (1, false),
+        4 => (1, true),
+        6 => (2, false),
+        8 => (2, true),
+        _ => return None,
+    };
+    Some(Color {
+        r: channel(0, width)?,
+        g: channel(1, width)?,
+        b: channel(2, width)?,
+        a: if alpha { channel(3, width)? } else { 0xff },
+    })
+}
+
+/// Reads an optional hex color from a VS Code theme.
+fn hex_field(
+    object: &Map<String, Json>,
+    key: &str,
+    path: &str,
+) -> Result<Option<Color>, LoadError> {
+    match object.get(key).and_then(Json::as_str) {
+        None => Ok(None),
+        Some(value) => hex(value).map(Some).ok_or_else(|| LoadError::Invalid {
+            path: format!("{}.{}", path, key),
+            reason: format!("invalid color `{}`", value),
+        }),
+    }
+}
+
+/// Reads a scope selector, written either as one string or as a list of them.
+fn selectors(scope: &Json, path: &str) -> Result<ScopeSelectors, LoadError> {
+    let scope = match scope {
+        Json::String(scope) => scope.clone(),
+        Json::Array(scopes) => scopes
+            .iter()
+            .filter_map(Json::as_str)
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => return Err(invalid(path, "scopes that are not strings")),
+    };
+    ScopeSelectors::from_str(&scope).map_err(|err| LoadError::Invalid {
+        path: path.into(),
+        reason: format!("invalid scope selector `{}`: {:?}", scope, err),
+    })
+}
+
+/// Reads a font style such as `"bold italic"`. Styles that cannot be rendered,
+/// such as `strikethrough`, are ignored.
+fn font_style(style: &str) -> FontStyle {
+    style
+        .split_whitespace()
+        .fold(FontStyle::empty(), |style, word| match word {
+            "bold" => style | FontStyle::BOLD,
+            "italic" => style | FontStyle::ITALIC,
+            "underline" => style | FontStyle::UNDERLINE,
+            _ => style,
+        })
+}
+
+/// Finds a theme setting by its name in a `.sublime-color-scheme`.
+fn field<'s>(settings: &'s mut ThemeSettings, key: &str) -> Option<&'s mut Option<Color>> {
+    Some(match key {
+        "foreground" => &mut settings.foreground,
+        "background" => &mut settings.background,
+        "caret" => &mut settings.caret,
+        "line_highlight" => &mut settings.line_highlight,
+        "misspelling" => &mut settings.misspelling,
+        "minimap_border" => &mut settings.minimap_border,
+        "accent" => &mut settings.accent,
+        "bracket_contents_foreground" => &mut settings.bracket_contents_foreground,
+        "brackets_foreground" => &mut settings.brackets_foreground,
+        "brackets_background" => &mut settings.brackets_background,
+        "tags_foreground" => &mut settings.tags_foreground,
+        "highlight" => &mut settings.highlight,
+        "find_highlight" => &mut settings.find_highlight,
+        "find_highlight_foreground" => &mut settings.find_highlight_foreground,
+        "gutter" => &mut settings.gutter,
+        "gutter_foreground" => &mut settings.gutter_foreground,
+        "selection" => &mut settings.selection,
+        "selection_foreground" => &mut settings.selection_foreground,
+        "selection_border" => &mut settings.selection_border,
+        "inactive_selection" => &mut settings.inactive_selection,
+        "inactive_selection_foreground" => &mut settings.inactive_selection_foreground,
+        "guide" => &mut settings.guide,
+        "active_guide" => &mut settings.active_guide,
+        "stack_guide" => &mut settings.stack_guide,
+        "shadow" => &mut settings.shadow,
+        _ => return None,
+    })
+}
+
+/// Removes the comments and trailing commas that editors allow in JSON. A
+/// removed comma is replaced by a space, so that positions in parse errors
+/// stay accurate.
+fn strip_comments(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    let mut chars = json.chars().peekable();
+    // Where a comma was written that may turn out to be trailing.
+    let mut comma = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                comma = None;
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            ',' => {
+                comma = Some(out.len());
+                out.push(c);
+            }
+            '}' | ']' => {
+                if let Some(at) = comma.take() {
+                    out.replace_range(at..=at, " ");
+                }
+                out.push(c);
+            }
+            c if c.is_whitespace() => out.push(c),
+            c => {
+                comma = None;
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+fn invalid(path: &str, construct: &str) -> LoadError {
+    LoadError::Invalid {
+        path: path.into(),
+        reason: format!("{} are not supported", construct),
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::TmTheme(err) => Display::fmt(err, fmt),
+            Self::Json(err) => write!(fmt, "invalid JSON theme: {}", err),
+            Self::Invalid { path, reason } => write!(fmt, "{}: {}", path, reason),
+        }
+    }
+}
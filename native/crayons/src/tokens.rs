@@ -0,0 +1,61 @@
+//! Highlighted text as BEAM terms, for callers that render it themselves.
+
+use rustler::{Atom, Encoder};
+
+use syntect::highlighting::{Color, FontStyle, Highlighter, Style, Theme};
+
+use crate::{atoms, render::LineState, syntaxes::Grammar};
+
+/// A run of text sharing one style.
+#[derive(rustler::NifMap, Clone, Debug)]
+pub struct Token {
+    pub text: String,
+    /// The `{r, g, b, a}` foreground color.
+    pub foreground: (u8, u8, u8, u8),
+    /// The `{r, g, b, a}` background color.
+    pub background: (u8, u8, u8, u8),
+    /// Some of the atoms `:bold`, `:italic`, and `:underline`.
+    pub font_style: Vec<Atom>,
+}
+
+impl Token {
+    fn new(style: Style, text: &str) -> Self {
+        let mut font_style = vec![];
+        if style.font_style.contains(FontStyle::BOLD) {
+            font_style.push(atoms::bold());
+        }
+        if style.font_style.contains(FontStyle::ITALIC) {
+            font_style.push(atoms::italic());
+        }
+        if style.font_style.contains(FontStyle::UNDERLINE) {
+            font_style.push(atoms::underline());
+        }
+        Self {
+            text: text.to_owned(),
+            foreground: rgba(style.foreground),
+            background: rgba(style.background),
+            font_style,
+        }
+    }
+}
+
+/// Highlights text into a list of tokens for each of its lines. Line endings
+/// are not included in any token.
+pub fn tokenize(grammar: &Grammar, theme: &Theme, text: &str) -> Vec<Vec<Token>> {
+    let highlighter = Highlighter::new(theme);
+    let mut state = LineState::new(grammar, theme);
+    text.lines()
+        .map(|line| {
+            state
+                .styled(grammar.set, &highlighter, line)
+                .into_iter()
+                .filter(|(_, text)| !text.is_empty())
+                .map(|(style, text)| Token::new(style, text))
+                .collect()
+        })
+        .collect()
+}
+
+fn rgba(Color { r, g, b, a }: Color) -> (u8, u8, u8, u8) {
+    (r, g, b, a)
+}
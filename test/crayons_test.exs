@@ -2,9 +2,33 @@ defmodule CrayonsTest do
   use ExUnit.Case
   doctest Crayons
 
-  test "leaves unknown text alone" do
-    assert {:ok, "Hello, world!"} = "Hello, world!" |> Crayons.color(:unknown, format: :terminal)
-    assert {:ok, "Hello, world!"} = "Hello, world!" |> Crayons.color(:unknown, format: :html)
+  test "falls back to safe plaintext for unknown languages" do
+    assert {:fallback, "Hello, world!", "Plain Text"} =
+             "Hello,\e[31m world!\a" |> Crayons.color(:unknown, format: :terminal)
+
+    assert {:fallback, "a\nbcd", "Plain Text"} =
+             "a\e\nb\e(Bc\e]0;title\ad" |> Crayons.color(:unknown, format: :terminal)
+
+    assert {:fallback,
+            "<pre style=\"background-color:#002b36;\">\n<span style=\"color:#839496;\">&lt;b&gt;hi&lt;/b&gt;</span></pre>\n",
+            "Plain Text"} = "<b>hi</b>" |> Crayons.color(:unknown, format: :html)
+  end
+
+  test "follows the unknown-language policy" do
+    assert {:error, :unknown_lang, %{value: "rsut", suggestions: ["Rust" | _]}} =
+             "fn main() {}" |> Crayons.color("rsut", unknown_lang: :error)
+
+    assert {:error, :undetected_lang, _} = "hello" |> Crayons.color(:auto, unknown_lang: :error)
+    assert {:ok, _} = "fn main() {}" |> Crayons.color(:rust, unknown_lang: :error)
+
+    python = "#!/usr/bin/env python3\nprint(1)\n"
+    assert {:fallback, colored, "Python"} =
+             python |> Crayons.color("pyton", unknown_lang: :detect)
+
+    assert {:ok, ^colored} = python |> Crayons.color(:python)
+    assert {:fallback, _, "Plain Text"} = "hello" |> Crayons.color("nope", unknown_lang: :detect)
+
+    assert {:error, :unknown_lang, _} = Crayons.stream_new("rsut", unknown_lang: :error)
   end
 
   test "adds HTML even to plaintext" do
@@ -13,6 +37,449 @@ defmodule CrayonsTest do
              "Hello, world!" |> Crayons.color(nil, format: :html)
   end
 
+  test "emits prefixed classes and a matching stylesheet" do
+    assert {:ok,
+            "<pre class=\"hl-code\">\n<span class=\"hl-text hl-plain\">a &lt; b</span></pre>\n"} =
+             "a < b" |> Crayons.color(nil, format: :html_classed, class_prefix: "hl-")
+
+    assert {:ok, css} = Crayons.css_for_theme("Solarized (dark)", class_prefix: "hl-")
+    assert css =~ ".hl-code { color: #839496; background-color: #002b36; }"
+
+    bad = "\"><script>"
+
+    assert {:error, :invalid_option, %{value: ^bad, option: :class_prefix}} =
+             "a" |> Crayons.color(nil, format: :html_classed, class_prefix: bad)
+
+    assert {:error, :invalid_option, _} =
+             Crayons.css_for_theme("Solarized (dark)", class_prefix: bad)
+
+    grammar = "name: Cpp\nscope: source.c++\ncontexts:\n  main: []\n"
+    assert {:ok, "Cpp"} = grammar |> Crayons.add_lang()
+
+    assert {:ok, "<pre class=\"code\">\n<span class=\"source c__\">x</span></pre>\n"} =
+             "x" |> Crayons.color("cpp", format: :html_classed)
+  end
+
+  test "colors the same way on either scheduler" do
+    text = String.duplicate("fn main() {}\n", 1000)
+    assert {:ok, dirty} = text |> Crayons.color(:rust, schedule: :dirty)
+    assert {:ok, ^dirty} = text |> Crayons.color(:rust, schedule: :normal)
+    assert {:ok, ^dirty} = text |> Crayons.color(:rust)
+  end
+
+  test "highlights Elixir and its templates out of the box" do
+    assert {:ok, html} = "defmodule Foo do\nend\n" |> Crayons.color(:elixir)
+    assert html =~ ~s(<span style="color:#859900;">defmodule</span>)
+    assert {:ok, _} = "<.button>{@label}</.button>" |> Crayons.color(:heex)
+    assert {:ok, _} = "iex(1)> 1 + 1\n2\n" |> Crayons.color(:iex)
+  end
+
+  test "streams match whole-text coloring" do
+    text = "fn main() {\n    println!(\"hi\");\n}\n"
+    {:ok, whole} = text |> Crayons.color(:rust, format: :html_classed)
+
+    {:ok, stream} = Crayons.stream_new(:rust, format: :html_classed)
+    {:ok, first} = Crayons.stream_push(stream, "fn main() {\n    print")
+    {:ok, second} = Crayons.stream_push(stream, "ln!(\"hi\");\n}\n")
+    {:ok, last} = Crayons.stream_finish(stream)
+
+    assert whole == first <> second <> last
+    assert {:error, :stream_finished, _} = Crayons.stream_push(stream, "more")
+
+    assert {:error, :invalid_option, %{option: :class_prefix}} =
+             Crayons.stream_new(:rust, format: :html_classed, class_prefix: "\"><")
+  end
+
+  test "produces tokens grouped by line" do
+    assert {:ok, [[%{text: "fn", foreground: {_, _, _, 255}, font_style: style} | _], []]} =
+             "fn main() {}\n\n" |> Crayons.color(:rust, format: :tokens)
+
+    assert is_list(style)
+    assert {:fallback, [[%{text: "<b>"}]], "Plain Text"} =
+             "<b>" |> Crayons.color(:x, format: :tokens)
+  end
+
+  test "reports scope stacks with byte ranges" do
+    text = "fn main() {}"
+    assert {:ok, tokens} = text |> Crayons.scopes(:rust)
+    assert Enum.map_join(tokens, &binary_part(text, elem(&1.range, 0), elem(&1.range, 1))) == text
+
+    assert Enum.any?(tokens, fn %{range: {offset, length}, scopes: scopes} ->
+             binary_part(text, offset, length) == "main" and
+               List.last(scopes) == "entity.name.function.rust"
+           end)
+  end
+
+  test "numbers lines in a gutter" do
+    text = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n"
+
+    assert {:ok, html} =
+             text |> Crayons.color(:txt, format: :html_classed, line_numbers: true, line_start: 9)
+
+    assert html =~ ~s(<span class="gutter" id="L9"><a href="#L9"> 9</a> </span>)
+    assert html =~ ~s(<a href="#L18">18</a>)
+    assert {:ok, css} = Crayons.css_for_theme("Solarized (dark)")
+    assert css =~ ".code .gutter { user-select: none;"
+
+    assert {:ok, ansi} =
+             "a\nb" |> Crayons.color(:txt, format: :terminal, line_numbers: true, line_separator: "|")
+
+    assert ansi =~ ~r/\e\[0;2[;0-9]*m1\|\e\[0m/
+  end
+
+  test "marks lines" do
+    text = "a\nb\nc\n"
+    opts = [format: :html_classed, mark_lines: [highlight: [1], focus: 2..3, deleted: 3]]
+    assert {:ok, html} = text |> Crayons.color(:txt, opts)
+
+    assert html =~ ~s(<span class="line line-highlight line-dimmed">)
+    assert html =~ ~s(<span class="line line-deleted">)
+    assert {:ok, css} = Crayons.css_for_theme("Solarized (dark)")
+    assert css =~ ".code .line-deleted { background-color: #"
+
+    assert {:ok, ansi} = text |> Crayons.color(:txt, format: :terminal, mark_lines: [inserted: 2])
+    assert ansi =~ ~r/\e\[48;2;\d+;\d+;\d+m.*b\e\[K\e\[0m/
+  end
+
+  test "reduces colors for shallower terminals" do
+    text = "fn main() {}"
+    assert {:ok, ansi} = text |> Crayons.color(:rust, format: :terminal_256)
+    assert ansi =~ ~r/\e\[38;5;\d+mfn/
+    refute ansi =~ "38;2;"
+
+    assert {:ok, ansi} = text |> Crayons.color(:rust, format: :terminal_16)
+    assert ansi =~ ~r/\e\[(3[0-7]|9[0-7])mfn/
+    refute ansi =~ ~r/\e\[[34]8;/
+
+    assert {:ok, ansi} = text |> Crayons.color(:rust, format: :terminal_mono)
+    assert String.replace(ansi, ~r/\e\[[0-9;]*m/, "") == text
+    refute ansi =~ ~r/\e\[[0-9;]*[34]8;/
+  end
+
+  test "keeps line endings exactly in terminal output" do
+    for text <- ["fn a() {}\r\nfn b() {}\n\n", "let x = 1;\rlet y = 2;\r", "\n", ""] do
+      assert {:ok, ansi} = text |> Crayons.color(:rust, format: :terminal)
+      assert String.replace(ansi, ~r/\e\[[0-9;]*m/, "") == text
+    end
+
+    text = "fn a() {}\nfn b() {}\n"
+    assert {:ok, ansi} = text |> Crayons.color(:rust, format: :terminal, reset_lines: true)
+    assert [_, _, _] = lines = String.split(ansi, "\n")
+    assert lines |> Enum.drop(-1) |> Enum.all?(&String.ends_with?(&1, "\e[0m"))
+  end
+
+  test "colors with the terminal's own palette" do
+    text = "fn main() {} // hi"
+    assert {:ok, ansi} = text |> Crayons.color(:rust, format: :terminal_ansi)
+    assert ansi =~ "\e[35mfn"
+    assert ansi =~ "\e[90m//"
+    refute ansi =~ ~r/\e\[[34]8;/
+
+    palette = [{"comment", :yellow}, {:"entity.name.function", :bright_cyan}]
+    assert {:ok, ansi} =
+             text |> Crayons.color(:rust, format: :terminal_ansi, ansi_palette: palette)
+
+    assert ansi =~ "\e[33m//"
+    assert ansi =~ "\e[96mmain"
+
+    palette = [{"a.b.c.d.e.f.g.h.i", :red}]
+
+    assert {:error, :invalid_palette, %{value: "a.b.c.d.e.f.g.h.i"}} =
+             text |> Crayons.color(:rust, format: :terminal_ansi, ansi_palette: palette)
+  end
+
+  test "detects languages" do
+    assert {:ok, "Elixir", :extension} = Crayons.detect_lang("", "lib/crayons.ex")
+    assert {:ok, "Ruby", :filename} = Crayons.detect_lang("", "Gemfile")
+    assert {:ok, "Python", :shebang} = Crayons.detect_lang("#!/usr/bin/env python3\n")
+    assert {:ok, "Ruby", :modeline} = Crayons.detect_lang("puts 1\n# vim: set ft=ruby:\n", "x.txt")
+    assert {:error, :undetected_lang, %{value: nil}} = Crayons.detect_lang("hello")
+
+    for name <- ["Old Just", "New Just"] do
+      grammar =
+        "name: #{name}\nscope: source.just\nfile_extensions: [Justfile]\n" <>
+          "contexts:\n  main: []\n"
+
+      assert {:ok, ^name} = grammar |> Crayons.add_lang()
+    end
+
+    assert {:ok, "New Just", :filename} = Crayons.detect_lang("", "Justfile")
+
+    assert {:ok, _} = "fn main() {}" |> Crayons.color({:path, "src/main.rs"})
+    assert {:fallback, _, "Plain Text"} = "hello" |> Crayons.color(:auto)
+  end
+
+  test "parses grammars in the newline mode they were written for" do
+    grammar = """
+    %YAML 1.2
+    ---
+    name: Hashy
+    scope: source.hashy
+    file_extensions: [hashy]
+    contexts:
+      main:
+        - match: '#.*\\n'
+          scope: comment.line.hashy
+    """
+
+    assert {:ok, "Hashy"} = grammar |> Crayons.add_lang(nil, with_newlines: true)
+
+    text = "a # hi\nb\n"
+    assert {:ok, tokens} = text |> Crayons.scopes("hashy")
+
+    assert [{"a ", ["source.hashy"]}, {"# hi", ["source.hashy", "comment.line.hashy"]},
+            {"b", ["source.hashy"]}] =
+             Enum.map(tokens, fn %{range: {offset, length}, scopes: scopes} ->
+               {binary_part(text, offset, length), scopes}
+             end)
+
+    assert {:ok, [_, [_]]} = "x # y\nz" |> Crayons.color("hashy", format: :tokens)
+  end
+
+  test "imports TextMate grammars" do
+    grammar = ~S"""
+    {
+      "name": "Toy",
+      "scopeName": "source.toy",
+      "fileTypes": ["toy"],
+      "patterns": [{"include": "#string"}, {"match": "#.*$", "name": "comment.line.toy"}],
+      "repository": {
+        "string": {
+          "begin": "\"",
+          "end": "\"",
+          "name": "string.quoted.toy",
+          "patterns": [{"match": "\\\\.", "name": "constant.character.escape.toy"}]
+        }
+      }
+    }
+    """
+
+    assert {:ok, "Toy"} = Crayons.add_lang(grammar)
+
+    text = ~S|"a\"b" # c|
+    assert {:ok, tokens} = text |> Crayons.scopes({:path, "x.toy"})
+
+    assert [{~S|"a|, [_, "string.quoted.toy"]},
+            {~S|\"|, [_, "string.quoted.toy", "constant.character.escape.toy"]},
+            {~S|b"|, [_, "string.quoted.toy"]}, {" ", ["source.toy"]},
+            {"# c", [_, "comment.line.toy"]}] =
+             Enum.map(tokens, fn %{range: {offset, length}, scopes: scopes} ->
+               {binary_part(text, offset, length), scopes}
+             end)
+
+    unsupported = ~S"""
+    {"scopeName": "source.bad", "patterns": [{"begin": ">", "while": ">"}]}
+    """
+
+    assert {:error, :invalid_lang_defn, %{message: message}} = Crayons.add_lang(unsupported)
+    assert message =~ "`while` rules at patterns[0]"
+  end
+
+  test "imports VS Code themes and Sublime color schemes" do
+    vscode = """
+    {
+      // Comments and trailing commas are allowed.
+      "name": "Toy Dark",
+      "colors": {"editor.background": "#1e1e1e", "editor.foreground": "#d4d4d4",},
+      "tokenColors": [{"scope": ["storage", "keyword"], "settings": {"foreground": "#c586c0"}}]
+    }
+    """
+
+    assert {:ok, "toy-dark"} = Crayons.add_theme(vscode, "toy-dark")
+    assert {:ok, html} = "fn main() {}" |> Crayons.color(:rust, theme: "toy-dark")
+    assert html =~ "background-color:#1e1e1e;"
+    assert html =~ "color:#c586c0;"
+
+    scheme = """
+    {
+      "variables": {"base": "hsl(0, 0%, 12.5%)", "bg": "var(base)"},
+      "globals": {"background": "var(bg)", "foreground": "rgb(200, 200, 200)"},
+      "rules": [{"scope": "storage", "foreground": "#569cd6"}]
+    }
+    """
+
+    assert {:ok, "toy-scheme"} = Crayons.add_theme(scheme, "toy-scheme")
+    assert {:ok, html} = "fn main() {}" |> Crayons.color(:rust, theme: "toy-scheme")
+    assert html =~ "background-color:#202020;"
+    assert html =~ "color:#569cd6;"
+
+    bad = ~S({"rules": [{"scope": "storage", "foreground": "var(missing)"}]})
+    assert {:error, :invalid_theme_defn, %{value: "bad", message: message}} =
+             Crayons.add_theme(bad, "bad")
+
+    assert message =~ "rules[0].foreground: unknown variable `missing`"
+  end
+
+  test "generates themes from Base16 schemes" do
+    scheme = """
+    scheme: "Tomorrow Night"
+    author: "Chris Kempson"
+    base00: "1d1f21"
+    base01: "282a2e"
+    base02: "373b41"
+    base03: "969896"
+    base04: "b4b7b4"
+    base05: "c5c8c6"
+    base06: "e0e0e0"
+    base07: "ffffff"
+    base08: "cc6666"
+    base09: "de935f"
+    base0A: "f0c674"
+    base0B: "b5bd68"
+    base0C: "8abeb7"
+    base0D: "81a2be"
+    base0E: "b294bb"
+    base0F: "a3685a"
+    """
+
+    assert {:ok, "tomorrow"} = Crayons.add_base16_theme(scheme, "tomorrow")
+    assert {:ok, html} = "fn main() {} // hi" |> Crayons.color(:rust, theme: "tomorrow")
+    assert html =~ "background-color:#1d1f21;"
+    assert html =~ "color:#b294bb;\">fn"
+    assert html =~ "color:#969896;\">//"
+
+    missing = String.replace(scheme, ~r/^base0F.*$/m, "")
+    assert {:error, :invalid_theme_defn, %{message: message}} = Crayons.add_base16_theme(missing, "x")
+    assert message =~ "base0F"
+  end
+
+  test "removes, replaces, and resets languages and themes" do
+    grammar = fn comment ->
+      """
+      %YAML 1.2
+      ---
+      name: Dotty
+      scope: source.dotty
+      file_extensions: [dotty]
+      contexts:
+        main:
+          - match: '#{comment}.*'
+            scope: comment.line.dotty
+      """
+    end
+
+    assert {:ok, "Dotty"} = grammar.(";") |> Crayons.add_lang()
+    assert {:ok, "Dotty"} = grammar.("%") |> Crayons.add_lang()
+    assert ["dotty"] = Crayons.list_langs() |> Enum.filter(&(&1 == "dotty"))
+    assert {:ok, tokens} = "% x" |> Crayons.scopes("dotty")
+    assert Enum.any?(tokens, &("comment.line.dotty" in &1.scopes))
+
+    assert {:error, :lang_conflict, %{value: "Dotty"}} =
+             grammar.(";") |> Crayons.add_lang(nil, on_conflict: :error)
+
+    assert {:ok, "Dotty"} = Crayons.remove_lang("dotty")
+    assert {:error, :lang_not_found, _} = Crayons.remove_lang("dotty")
+    assert {:error, :lang_not_found, %{message: message}} = Crayons.remove_lang("rust")
+    assert message =~ "built into syntect"
+
+    theme = ~S({"colors": {"editor.background": "#101010"}, "tokenColors": []})
+    assert {:ok, "Removable"} = Crayons.add_theme(theme, "Removable")
+    assert {:ok, "Removable"} = Crayons.remove_theme("Removable")
+    assert {:error, :unknown_theme, _} = Crayons.remove_theme("Removable")
+
+    assert {:ok, "Removable"} = Crayons.add_theme(theme, "Removable")
+    assert {:ok, "Dotty"} = grammar.(";") |> Crayons.add_lang()
+    assert :ok = Crayons.reset_registry()
+    refute "Removable" in Crayons.list_themes()
+    refute "dotty" in Crayons.list_langs()
+    assert "Solarized (dark)" in Crayons.list_themes()
+  end
+
+  test "adds languages in batches, all or nothing" do
+    grammar = fn name ->
+      """
+      %YAML 1.2
+      ---
+      name: #{name}
+      scope: source.#{String.downcase(name)}
+      contexts:
+        main: []
+      """
+    end
+
+    assert {:error, :invalid_lang_defn, %{errors: [{1, _}]}} =
+             Crayons.add_langs([grammar.("Batch One"), "contexts: [", grammar.("Batch Two")])
+
+    refute "batch one" in Crayons.list_langs()
+
+    assert {:error, :lang_conflict, %{value: "Batch One"}} =
+             [grammar.("Batch One"), grammar.("Batch One")]
+             |> Crayons.add_langs(on_conflict: :error)
+
+    assert {:ok, ["Batch One", "Batch Two"]} =
+             [grammar.("Batch One"), {grammar.("Batch Two"), nil, with_newlines: true}]
+             |> Crayons.add_langs()
+
+    assert ["batch one", "batch two"] =
+             Crayons.list_langs() |> Enum.filter(&String.starts_with?(&1, "batch"))
+  end
+
+  test "loads Sublime packages from directories and archives" do
+    dir = Path.join(System.tmp_dir!(), "crayons-package-#{System.unique_integer([:positive])}")
+    File.mkdir_p!(Path.join(dir, "Syntaxes"))
+
+    grammar = fn name -> "name: #{name}\nscope: source.#{name}\ncontexts:\n  main: []\n" end
+    File.write!(Path.join(dir, "Syntaxes/Pkgdir.sublime-syntax"), grammar.("pkgdir"))
+    File.write!(Path.join(dir, "Syntaxes/Broken.sublime-syntax"), "contexts: [")
+    File.write!(Path.join(dir, "Comments.tmPreferences"), "<plist/>")
+
+    File.write!(Path.join(dir, "Pkgdir.sublime-color-scheme"), """
+    {"globals": {"background": "#102030", "foreground": "#ffffff"}, "rules": []}
+    """)
+
+    File.write!(Path.join(dir, "Pkgdir.tmTheme"), "<plist/>")
+
+    {:ok, _} =
+      :zip.create(String.to_charlist(Path.join(dir, "Zipped.sublime-package")), [
+        {'Pkgzip.sublime-syntax', grammar.("pkgzip")}
+      ])
+
+    assert {:ok, report} = Crayons.load_package(dir)
+    assert %{langs: ["pkgdir", "pkgzip"], themes: ["Pkgdir"]} = report
+    assert [{"Pkgdir.tmTheme", _}, {"Syntaxes/Broken.sublime-syntax", _}] = report.failed
+    assert ["Comments.tmPreferences"] = report.skipped
+    assert {:ok, html} = "x" |> Crayons.color("pkgzip", theme: "Pkgdir")
+    assert html =~ "#102030"
+
+    assert {:ok, %{langs: ["pkgzip"]}} =
+             dir |> Path.join("Zipped.sublime-package") |> Crayons.load_package()
+
+    assert {:error, :invalid_package, _} = dir |> Path.join("missing") |> Crayons.load_package()
+    File.rm_rf!(dir)
+  end
+
+  test "dumps and restores the registry" do
+    grammar = "name: Dumped\nscope: source.dumped\ncontexts:\n  main: []\n"
+    assert {:ok, "Dumped"} = grammar |> Crayons.add_lang()
+    assert {:ok, dump} = Crayons.dump_registry()
+
+    assert {:ok, "Dumped"} = Crayons.remove_lang("dumped")
+    assert :ok = Crayons.load_registry(dump)
+    assert "dumped" in Crayons.list_langs()
+    assert {:ok, _} = "x" |> Crayons.color("dumped")
+
+    assert {:error, :invalid_dump, _} = Crayons.load_registry("not a dump")
+    assert "dumped" in Crayons.list_langs()
+    assert {:ok, "Dumped"} = Crayons.remove_lang("dumped")
+  end
+
+  test "suggests known names in errors" do
+    assert {:error, :unknown_theme, details} = "x" |> Crayons.color(:rust, theme: "solarized")
+    assert %{value: "solarized", suggestions: ["Solarized (dark)", "Solarized (light)"]} = details
+    assert details.message =~ "solarized"
+
+    assert {:error, :unknown_format, %{value: :htm, suggestions: ["html" | _]}} =
+             "x" |> Crayons.color(:rust, format: :htm)
+
+    assert {:error, :unknown_format, %{suggestions: ["tokens"]}} =
+             "x" |> Crayons.color(:rust, format: :token)
+
+    assert {:error, :unknown_format, %{suggestions: []}} =
+             Crayons.stream_new(:rust, format: :token)
+
+    assert {:error, :unknown_theme, %{suggestions: []}} = Crayons.remove_theme("zzzzzz")
+  end
+
   test "can load new definitions" do
     name = "testing"
     assert nil == Crayons.list_themes |> Enum.find(fn theme -> theme == name end)